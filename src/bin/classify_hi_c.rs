@@ -10,27 +10,25 @@ use self::rust_htslib::prelude::*;
 type BoxResult<T> = Result<T, Box<error::Error>>;
 
 #[derive(Debug)]
-struct TagError {
+struct SimpleError {
     message: String,
 }
 
-impl TagError {
-    fn new() -> Box<TagError> {
-        Box::new(TagError {
-            message: "Cannot find alignment score (AS) tag in \
-                alignment. Try using bwa mem or another aligner \
-                that outputs these tags.".to_string(),
+impl SimpleError {
+    fn new(message: String) -> Box<SimpleError> {
+        Box::new(SimpleError {
+            message: message,
         })
     }
 }
 
-impl fmt::Display for TagError {
+impl fmt::Display for SimpleError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{}", self.message)
     }
 }
 
-impl error::Error for TagError {}
+impl error::Error for SimpleError {}
 
 fn parse_args() -> ArgMatches<'static> {
     App::new("classify_hi_c")
@@ -63,140 +61,173 @@ fn parse_args() -> ArgMatches<'static> {
              .takes_value(true)
              .default_value("hapB")
              .help("Place to put haplotype B output bam"))
+        .arg(Arg::with_name("hapU-out")
+             .short("U")
+             .long("hapU-out")
+             .required(true)
+             .takes_value(true)
+             .default_value("hapU")
+             .help("Prefix for the ambiguous-read output bams. Two files \
+                 are written, \"<prefix>_A.bam\" and \"<prefix>_B.bam\", \
+                 one in each input's own coordinate system, since a read \
+                 pair's alignments to haplotype A and haplotype B don't \
+                 share a header"))
+        .arg(Arg::with_name("margin")
+             .short("m")
+             .long("margin")
+             .takes_value(true)
+             .default_value("0")
+             .help("A read is classified as ambiguous, rather than being \
+                 assigned to whichever haplotype scored higher, if its \
+                 total alignment scores in the two haplotypes differ by \
+                 no more than this"))
         .get_matches()
 }
 
-/// If the read ID's of current_record_a and current_record_b are not the same,
-/// advance the reader that is behind the other one until they are the same.
-/// This function can return three different things:
-/// 1. Ok(false) if everything went fine and neither reader is at EOF
-/// 2. Ok(true) if one of the readers is at EOF
-/// 3. Err if there's an error reading or writing the bam files
-fn advance_until(in_bam_a: &mut bam::Reader,
-                 in_bam_b: &mut bam::Reader,
-                 out_bam_a: &mut bam::Writer,
-                 out_bam_b: &mut bam::Writer,
-                 current_record_a: &mut bam::Record,
-                 current_record_b: &mut bam::Record) -> BoxResult<bool> {
-
-    while current_record_a.qname() > current_record_b.qname() {
-        // if our iteration of A is ahead of B, advance B until it catches
-        // up, outputting the unpaired records
-        out_bam_b.write(current_record_b)?;
-        if read_or_eof(in_bam_b, current_record_b)? {
-            // make current_record_b empty so we don't print it twice
-            current_record_b.set_qname(b"EOF");
-            return Ok(true)
-        }
+/// Reads the next record off `reader` into a freshly-allocated
+/// `bam::Record`. Returns `Ok(None)` at EOF instead of a `ReadError`.
+fn read_one(reader: &mut bam::Reader) -> BoxResult<Option<bam::Record>> {
+    let mut record = bam::Record::new();
+    match reader.read(&mut record) {
+        Ok(_) => Ok(Some(record)),
+        Err(bam::ReadError::NoMoreRecord) => Ok(None),
+        Err(e) => Err(Box::new(e)),
     }
+}
 
-    while current_record_b.qname() > current_record_a.qname() {
-        // if our iteration of B is ahead of A, advance A until it catches
-        // up, outputting the unpaired records
-        out_bam_a.write(&current_record_a)?;
-        if read_or_eof(in_bam_a, current_record_a)? {
-            // set qname of current record to "EOF" so we know we are at the
-            // end of the file ugh ugh ugh this is so ugly sorry
-            current_record_a.set_qname(b"EOF");
-            return Ok(true)
+/// Reads every subsequent record off `reader` that shares `first`'s qname,
+/// collecting them together with `first` into one group: a name-sorted bam
+/// can have more than one record per read name (secondary/supplementary
+/// alignments, or both mates of a pair), and all of them need to be scored
+/// and classified together rather than one at a time. Returns the group
+/// along with the first record of the following group, or `None` at EOF.
+fn read_qname_group(reader: &mut bam::Reader, first: bam::Record)
+        -> BoxResult<(Vec<bam::Record>, Option<bam::Record>)> {
+    let qname = first.qname().to_vec();
+    let mut group = vec![first];
+
+    loop {
+        match read_one(reader)? {
+            Some(record) => {
+                if record.qname() == &qname[..] {
+                    group.push(record);
+                } else {
+                    return Ok((group, Some(record)));
+                }
+            }
+            None => return Ok((group, None)),
         }
     }
+}
 
-    return Ok(false)
+/// Approximates an alignment's score from its CIGAR and edit distance, for
+/// use when the aligner didn't provide an "AS" tag: the number of aligned
+/// (CIGAR "M") bases minus the "NM" edit distance, so mismatches and indels
+/// both count against the score roughly the way an aligner's own AS score
+/// would.
+fn fallback_score(record: &bam::Record) -> i64 {
+    let aligned_len: i64 = record.cigar().iter()
+        .filter(|c| c.char() == 'M')
+        .map(|c| i64::from(c.len()))
+        .sum();
+    let edit_distance = record.aux(b"NM").map(|a| a.integer()).unwrap_or(0);
+
+    aligned_len - edit_distance
 }
 
-/// Read the next record in `in_bam` into `record`. Three things can happen:
-/// 1. Next record is successfully read. Return Ok(false).
-/// 2. Can't read next record because EOF! Return Ok(true).
-/// 3. Can't read next record because of some other problem. Return the error.
-fn read_or_eof(in_bam: &mut bam::Reader,
-               record: &mut bam::Record) -> BoxResult<bool> {
-    match in_bam.read(record) {
-        Ok(_) => return Ok(false),
-        Err(e) => {
-            if let bam::ReadError::NoMoreRecord = e {
-                return Ok(true)
-            } else {
-                return Err(Box::new(e))
-            }
-        }
+/// Returns a record's alignment score: the "AS" tag if the aligner wrote
+/// one, or else `fallback_score`.
+fn alignment_score(record: &bam::Record) -> i64 {
+    record.aux(b"AS").map(|a| a.integer())
+        .unwrap_or_else(|| fallback_score(record))
+}
+
+/// Sums `alignment_score` over every record in a qname group, so a read
+/// with more than one alignment or mate contributes one combined score
+/// instead of being compared one alignment at a time.
+fn group_score(group: &[bam::Record]) -> i64 {
+    group.iter().map(alignment_score).sum()
+}
+
+fn write_group(out_bam: &mut bam::Writer, group: &[bam::Record]) -> BoxResult<()> {
+    for record in group {
+        out_bam.write(record)?;
     }
+    Ok(())
 }
 
+/// Classifies Hi-C reads by comparing, for each read name, the total
+/// alignment score of all of its records in `in_bam_a` against the total in
+/// `in_bam_b`. The higher-scoring side's records go to its output bam; if
+/// the two totals differ by no more than `margin`, both sides' records go
+/// to the ambiguous outputs instead, so downstream tools can treat
+/// genuinely ambiguous reads separately rather than finding them
+/// duplicated into both haplotypes. Read names present in only one input
+/// are written straight through to that input's own output.
 fn classify_hi_c(in_bam_a: &mut bam::Reader,
                  in_bam_b: &mut bam::Reader,
                  out_bam_a: &mut bam::Writer,
-                 out_bam_b: &mut bam::Writer) -> BoxResult<()> {
-
-    // allocate new empty bam records to store actual records
-    let mut current_record_a = bam::Record::new();
-    let mut current_record_b = bam::Record::new();
-
-    let mut score_a: i64;
-    let mut score_b: i64;
-
-    // TODO have some check that the file is sorted by read name, or else the
-    // program may exit without error but output empty files, which would be bad
-
-    let mut eof_a = read_or_eof(in_bam_a, &mut current_record_a)?;
-    let mut eof_b = read_or_eof(in_bam_b, &mut current_record_b)?;
-
-    // continue reading records until we reach EOF in one of the files
-    while !eof_a && !eof_b {
-        // we can only compare two alignments if they are of the same read, so
-        // if we are not looking at records describing the same read, we need to
-        // fix that. `advance_until` returns true if we've reached EOF of one of
-        // the bam files, so only do the score comparing stuff if it returns
-        // false.
-        if !advance_until(in_bam_a, in_bam_b,
-                          out_bam_a, out_bam_b,
-                          &mut current_record_a, &mut current_record_b)? {
-
-            // now that we have alignments of the same read in current_record_a
-            // and current_record_b, we can compare them. First, get the
-            // alignment scores from the "AS" tag of the bam record:
-            score_a = current_record_a.aux(b"AS")
-                .ok_or(TagError::new())
-                .map(|s| s.integer())?;
-            score_b = current_record_a.aux(b"AS")
-                .ok_or(TagError::new())
-                .map(|s| s.integer())?;
-
-            // then, output the higher-scoring alignment to its corresponding
-            // output file, or both if the scores are equal.
-            if score_a >= score_b {
-                out_bam_a.write(&current_record_a)?;
-            }
-
-            if score_b >= score_a {
-                out_bam_b.write(&current_record_b)?;
+                 out_bam_b: &mut bam::Writer,
+                 out_bam_u_a: &mut bam::Writer,
+                 out_bam_u_b: &mut bam::Writer,
+                 margin: i64) -> BoxResult<()> {
+    // TODO have some check that the files are sorted by read name, or else
+    // the program may exit without error but output empty files, which
+    // would be bad
+
+    let mut next_a = read_one(in_bam_a)?;
+    let mut next_b = read_one(in_bam_b)?;
+
+    loop {
+        match (next_a.take(), next_b.take()) {
+            (Some(record_a), Some(record_b)) => {
+                if record_a.qname() < record_b.qname() {
+                    // this read isn't in haplotype B's file at all
+                    let (group, next) = read_qname_group(in_bam_a, record_a)?;
+                    write_group(out_bam_a, &group)?;
+                    next_a = next;
+                    next_b = Some(record_b);
+                } else if record_b.qname() < record_a.qname() {
+                    // this read isn't in haplotype A's file at all
+                    let (group, next) = read_qname_group(in_bam_b, record_b)?;
+                    write_group(out_bam_b, &group)?;
+                    next_b = next;
+                    next_a = Some(record_a);
+                } else {
+                    // same read name on both sides: gather every record for
+                    // it on each side and compare their combined scores
+                    let (group_a, nxt_a) = read_qname_group(in_bam_a, record_a)?;
+                    let (group_b, nxt_b) = read_qname_group(in_bam_b, record_b)?;
+
+                    let score_a = group_score(&group_a);
+                    let score_b = group_score(&group_b);
+
+                    if (score_a - score_b).abs() <= margin {
+                        write_group(out_bam_u_a, &group_a)?;
+                        write_group(out_bam_u_b, &group_b)?;
+                    } else if score_a > score_b {
+                        write_group(out_bam_a, &group_a)?;
+                    } else {
+                        write_group(out_bam_b, &group_b)?;
+                    }
+
+                    next_a = nxt_a;
+                    next_b = nxt_b;
+                }
             }
-
-            eof_a = read_or_eof(in_bam_a, &mut current_record_a)?;
-            eof_b = read_or_eof(in_bam_b, &mut current_record_b)?;
-        } else { // advance_until reached EOF for one of the files, but which?
-            if current_record_a.qname() == b"EOF" {
-                eof_a = true;
+            (Some(record_a), None) => {
+                let (group, next) = read_qname_group(in_bam_a, record_a)?;
+                write_group(out_bam_a, &group)?;
+                next_a = next;
             }
-
-            if current_record_b.qname() == b"EOF" {
-                eof_b = true;
+            (None, Some(record_b)) => {
+                let (group, next) = read_qname_group(in_bam_b, record_b)?;
+                write_group(out_bam_b, &group)?;
+                next_b = next;
             }
+            (None, None) => return Ok(()),
         }
     }
-
-    // now that one of the files has reached EOF, we make sure both have
-    while !eof_a {
-        out_bam_a.write(&current_record_a)?;
-        eof_a = read_or_eof(in_bam_a, &mut current_record_a)?;
-    }
-
-    while !eof_b {
-        out_bam_b.write(&current_record_b)?;
-        eof_b = read_or_eof(in_bam_b, &mut current_record_b)?;
-    }
-
-    Ok(())
 }
 
 fn run() -> BoxResult<()> {
@@ -218,7 +249,23 @@ fn run() -> BoxResult<()> {
     let mut out_bam_b = bam::Writer::from_path(
         args.value_of("hapB-out").unwrap(), &header_b)?;
 
-    classify_hi_c(&mut in_bam_a, &mut in_bam_b, &mut out_bam_a, &mut out_bam_b)
+    // the two inputs don't share a header, so the ambiguous bin needs one
+    // output file per side, both named off the same --hapU-out prefix
+    let hap_u_prefix = args.value_of("hapU-out").unwrap();
+    let mut out_bam_u_a = bam::Writer::from_path(
+        &format!("{}_A.bam", hap_u_prefix), &header_a)?;
+    let mut out_bam_u_b = bam::Writer::from_path(
+        &format!("{}_B.bam", hap_u_prefix), &header_b)?;
+
+    let margin = args.value_of("margin").unwrap().parse::<i64>()
+        .map_err(|_| SimpleError::new(
+            format!("--margin argument not an integer: {}",
+                    args.value_of("margin").unwrap())))?;
+
+    classify_hi_c(&mut in_bam_a, &mut in_bam_b,
+                  &mut out_bam_a, &mut out_bam_b,
+                  &mut out_bam_u_a, &mut out_bam_u_b,
+                  margin)
 }
 
 fn main() {
@@ -1,5 +1,6 @@
 extern crate trio_binning;
 extern crate clap;
+extern crate num_cpus;
 
 use trio_binning::kmer::*;
 use trio_binning::classify::*;
@@ -39,8 +40,9 @@ fn parse_args() -> ArgMatches<'static> {
              .short("t")
              .long("threads")
              .takes_value(true)
-             .default_value("1")
-             .help("Number of threads to use"))
+             .default_value("auto")
+             .help("Number of threads to use, or \"auto\" (or 0) to use \
+                   every logical CPU on the machine"))
         .arg(Arg::with_name("hapA-kmers")
              .short("a")
              .long("hapA-kmers")
@@ -92,6 +94,20 @@ fn parse_args() -> ArgMatches<'static> {
              .short("c")
              .long("compress-output")
              .help("Output gz-compressed files"))
+        .arg(Arg::with_name("min-base-qual")
+             .short("q")
+             .long("min-base-qual")
+             .takes_value(true)
+             .help("Minimum Phred quality score required of every base in a \
+                   k-mer for it to be counted; has no effect on fasta input, \
+                   which carries no quality scores"))
+        .arg(Arg::with_name("confidence")
+             .long("confidence")
+             .takes_value(true)
+             .default_value("0")
+             .help("Minimum magnitude of the log-likelihood ratio between \
+                   haplotypes required to call a read/pair instead of \
+                   leaving it unclassified"))
         .get_matches()
 }
 
@@ -107,72 +123,150 @@ fn simple_error<E: 'static + error::Error>(e: E) -> SimpleError {
     }
 }
 
+/// Parses the `--threads` argument, treating `"auto"` or `"0"` as a request
+/// to use every logical CPU on the machine. This is the total thread budget
+/// handed to `classify_unpaired`, which reserves threads for reading and
+/// writing, leaving the rest for the counter pool. `classify_paired` and the
+/// `classify_*_wide` functions take no thread count and run on one thread.
+fn parse_num_threads(arg: &str) -> BoxResult<u32> {
+    match arg {
+        "auto" | "0" => Ok(num_cpus::get() as u32),
+        s => match s.parse::<u32>() {
+            Ok(t) if t >= 1 => Ok(t),
+            Ok(t) => Err(SimpleError::new(
+                format!("Number of threads must be >= 1: {}", t))),
+            Err(_) => Err(SimpleError::new(
+                format!("--threads argument not an integer: {}", s))),
+        },
+    }
+}
+
 fn run() -> BoxResult<()> {
     let args = parse_args();
 
     // get the number of threads to use
-    let num_threads = match args.value_of("threads").unwrap().parse::<u32>() {
-        Ok(t) => {
-            if t >= 1 { t } else {
-                return Err(SimpleError::new(
-                    format!("Number of threads must be >= 1: {}", t)
-                ))
-            }
-        },
-        Err(_) =>
-            return Err(SimpleError::new(
-                format!("--threads argument not an integer: {}",
-                        args.value_of("threads").unwrap())
-            )),
-    };
+    let num_threads = parse_num_threads(args.value_of("threads").unwrap())?;
 
     // figure out k by looking at the first line of one of the kmers file
     let k = get_kmer_size(File::open(args.value_of("hapA-kmers").unwrap())?)?;
 
-    // read k-mers into HashSets
-    let (hap_a_kmers, hap_b_kmers);
-    if num_threads > 1 { // trying out some concurrency!
-        let hap_a_kmers_filename = args.value_of("hapA-kmers")
-            .unwrap().to_string();
-
-        // read the kmers from haplotype A in a spawned thread
-        // error::Error does not implement Send, so the thread has to return a
-        // concrete error type, in this case, SimpleError.
-        let handle = thread::spawn(move ||
-            File::open(hap_a_kmers_filename).map_err(simple_error)
-                .and_then(|f| read_kmers_into_set(f).map_err(simple_error)));
-
-        // read the kmers from haplotype B in the main thread
-        hap_b_kmers = read_kmers_into_set(File::open(
-                args.value_of("hapB-kmers").unwrap())?)?;
+    // parse the minimum base quality filter, if the user gave one
+    let min_base_qual = match args.value_of("min-base-qual") {
+        Some(q) => Some(q.parse::<u8>().map_err(|_| SimpleError::new(
+            format!("--min-base-qual argument not an integer: {}", q)))?),
+        None => None,
+    };
+
+    // parse the confidence threshold for the log-likelihood-ratio call
+    let confidence = args.value_of("confidence").unwrap().parse::<f32>()
+        .map_err(|_| SimpleError::new(
+            format!("--confidence argument not a number: {}",
+                    args.value_of("confidence").unwrap())))?;
+
+    // k <= 32 fits in a u64 and gets the fast, incrementally-encoded
+    // classification path; wider k-mers (common with meryl/merqury, which
+    // often run in the 21-51 range) fall back to the generic, un-threaded
+    // path in classify_*_wide.
+    if k <= 32 {
+        // read k-mers into HashSets
+        let (hap_a_kmers, hap_b_kmers);
+        if num_threads > 1 { // trying out some concurrency!
+            let hap_a_kmers_filename = args.value_of("hapA-kmers")
+                .unwrap().to_string();
 
-        // wait to continue until the spawned thread is done
-        hap_a_kmers = handle.join().unwrap()?;
+            // read the kmers from haplotype A in a spawned thread
+            // error::Error does not implement Send, so the thread has to return a
+            // concrete error type, in this case, SimpleError.
+            let handle = thread::spawn(move ||
+                File::open(hap_a_kmers_filename).map_err(simple_error)
+                    .and_then(|f| read_kmers_into_set(f).map_err(simple_error)));
+
+            // read the kmers from haplotype B in the main thread
+            hap_b_kmers = read_kmers_into_set(File::open(
+                    args.value_of("hapB-kmers").unwrap())?)?;
+
+            // wait to continue until the spawned thread is done
+            hap_a_kmers = handle.join().unwrap()?;
+        } else {
+            hap_a_kmers = read_kmers_into_set(File::open(
+                    args.value_of("hapA-kmers").unwrap())?)?;
+            hap_b_kmers = read_kmers_into_set(File::open(
+                    args.value_of("hapB-kmers").unwrap())?)?;
+        }
+
+        // call the correct function depending on whether the input is unpaired
+        // reads or paired-end reads
+        match args.value_of("input-unpaired") {
+            Some(input_reads_filename) => {
+                classify_unpaired(hap_a_kmers, hap_b_kmers, input_reads_filename,
+                                  args.value_of("hapA-out-prefix").unwrap(),
+                                  args.value_of("hapB-out-prefix").unwrap(),
+                                  args.value_of("hapU-out-prefix").unwrap(),
+                                  args.is_present("compress-output"), k,
+                                  num_threads as usize, min_base_qual,
+                                  confidence)?;
+            }
+            None => {
+                let filenames: Vec<&str> = args.values_of("input-paired-end")
+                    .unwrap().collect();
+                classify_paired(&hap_a_kmers, &hap_b_kmers, filenames[0],
+                                filenames[1],
+                                args.value_of("hapA-out-prefix").unwrap(),
+                                args.value_of("hapB-out-prefix").unwrap(),
+                                args.value_of("hapU-out-prefix").unwrap(),
+                                args.is_present("compress-output"), k,
+                                min_base_qual, confidence)?;
+            }
+        }
     } else {
-        hap_a_kmers = read_kmers_into_set(File::open(
+        let hap_a_kmers = read_kmers_into_any_set(File::open(
                 args.value_of("hapA-kmers").unwrap())?)?;
-        hap_b_kmers = read_kmers_into_set(File::open(
+        let hap_b_kmers = read_kmers_into_any_set(File::open(
                 args.value_of("hapB-kmers").unwrap())?)?;
-    }
 
-    // call the correct function depending on whether the input is unpaired
-    // reads or paired-end reads
-    match args.value_of("input-unpaired") {
-        Some(input_reads_filename) => {
-            classify_unpaired(&hap_a_kmers, &hap_b_kmers, input_reads_filename,
-                              args.value_of("hapA-out-prefix").unwrap(),
-                              args.value_of("hapB-out-prefix").unwrap(),
-                              args.value_of("hapU-out-prefix").unwrap(),
-                              args.is_present("compress-output"), k)?;
-        }
-        None => {
-            let filenames: Vec<&str> = args.values_of("input-paired")
-                .unwrap().collect();
-            classify_paired(&hap_a_kmers, &hap_b_kmers, filenames[0],
-                            filenames[1],
+        match args.value_of("input-unpaired") {
+            Some(input_reads_filename) => match (hap_a_kmers, hap_b_kmers) {
+                (AnyKmerSet::Wide(a), AnyKmerSet::Wide(b)) =>
+                    classify_unpaired_wide(a, b, input_reads_filename,
+                        args.value_of("hapA-out-prefix").unwrap(),
+                        args.value_of("hapB-out-prefix").unwrap(),
+                        args.value_of("hapU-out-prefix").unwrap(),
+                        args.is_present("compress-output"), k, min_base_qual,
+                        confidence)?,
+                (AnyKmerSet::Huge(a), AnyKmerSet::Huge(b)) =>
+                    classify_unpaired_wide(a, b, input_reads_filename,
+                        args.value_of("hapA-out-prefix").unwrap(),
+                        args.value_of("hapB-out-prefix").unwrap(),
+                        args.value_of("hapU-out-prefix").unwrap(),
+                        args.is_present("compress-output"), k, min_base_qual,
+                        confidence)?,
+                _ => return Err(SimpleError::new(
+                    "hapA-kmers and hapB-kmers files imply different k-mer \
+                    lengths".to_string())),
+            },
+            None => {
+                let filenames: Vec<&str> = args.values_of("input-paired-end")
+                    .unwrap().collect();
+                match (hap_a_kmers, hap_b_kmers) {
+                    (AnyKmerSet::Wide(a), AnyKmerSet::Wide(b)) =>
+                        classify_paired_wide(&a, &b, filenames[0], filenames[1],
                             args.value_of("hapA-out-prefix").unwrap(),
                             args.value_of("hapB-out-prefix").unwrap(),
-                            args.value_of("hapU-out-prefix").unwrap())?;
+                            args.value_of("hapU-out-prefix").unwrap(),
+                            args.is_present("compress-output"), k,
+                            min_base_qual, confidence)?,
+                    (AnyKmerSet::Huge(a), AnyKmerSet::Huge(b)) =>
+                        classify_paired_wide(&a, &b, filenames[0], filenames[1],
+                            args.value_of("hapA-out-prefix").unwrap(),
+                            args.value_of("hapB-out-prefix").unwrap(),
+                            args.value_of("hapU-out-prefix").unwrap(),
+                            args.is_present("compress-output"), k,
+                            min_base_qual, confidence)?,
+                    _ => return Err(SimpleError::new(
+                        "hapA-kmers and hapB-kmers files imply different \
+                        k-mer lengths".to_string())),
+                }
+            }
         }
     }
     Ok(())
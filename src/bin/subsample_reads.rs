@@ -0,0 +1,163 @@
+extern crate trio_binning;
+extern crate clap;
+
+use trio_binning::subsample::*;
+use clap::{Arg, App, ArgGroup, ArgMatches};
+use std::{process, error, fmt};
+
+type BoxResult<T> = Result<T, Box<error::Error>>;
+
+#[derive(Debug)]
+struct SimpleError {
+    message: String,
+}
+
+impl SimpleError {
+    fn new(message: String) -> Box<SimpleError> {
+        Box::new(SimpleError {
+            message: message,
+        })
+    }
+}
+
+impl fmt::Display for SimpleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl error::Error for SimpleError {}
+
+fn parse_args() -> ArgMatches<'static> {
+    App::new("subsample_reads")
+        .version("0.1.0")
+        .author("Edward S. Rice <erice11@unl.edu>")
+        .about("Subsample reads down to a target coverage, to normalize \
+               depth across the trio before haplotype binning")
+        .arg(Arg::with_name("genome-size")
+             .short("g")
+             .long("genome-size")
+             .required(true)
+             .takes_value(true)
+             .help("Expected genome size in bases"))
+        .arg(Arg::with_name("coverage")
+             .short("c")
+             .long("coverage")
+             .required(true)
+             .takes_value(true)
+             .help("Target coverage to subsample down to, e.g. 30"))
+        .arg(Arg::with_name("total-bases")
+             .long("total-bases")
+             .takes_value(true)
+             .help("Total number of bases in the input, if already known; \
+                   saves a throwaway counting pass over the input reads"))
+        .arg(Arg::with_name("seed")
+             .short("s")
+             .long("seed")
+             .takes_value(true)
+             .default_value("0")
+             .help("Seed for the random number generator, for reproducible \
+                   subsampling"))
+        .arg(Arg::with_name("compress-output")
+             .long("compress-output")
+             .help("Output gz-compressed files"))
+        .group(ArgGroup::with_name("input-reads")
+               .args(&["input-unpaired", "input-paired-end"])
+               .required(true))
+        .arg(Arg::with_name("input-unpaired")
+             .short("u")
+             .long("input-unpaired")
+             .takes_value(true)
+             .help("Fasta/q/bam file containing unpaired reads to \
+                   subsample, e.g. PacBio"))
+        .arg(Arg::with_name("input-paired-end")
+             .short("p")
+             .long("input-paired-end")
+             .takes_value(true)
+             .number_of_values(2)
+             .help("A pair of fastq files containing paired reads to \
+                   subsample; mates are always kept or dropped together"))
+        .arg(Arg::with_name("output")
+             .short("o")
+             .long("output")
+             .required(true)
+             .takes_value(true)
+             .min_values(1)
+             .max_values(2)
+             .help("Output file, or (for --input-paired-end) both output \
+                   files in the same order as --input-paired-end"))
+        .get_matches()
+}
+
+fn run() -> BoxResult<()> {
+    let args = parse_args();
+
+    let genome_size = args.value_of("genome-size").unwrap().parse::<u64>()
+        .map_err(|_| SimpleError::new(
+            format!("--genome-size argument not an integer: {}",
+                    args.value_of("genome-size").unwrap())))?;
+
+    let coverage = args.value_of("coverage").unwrap().parse::<f64>()
+        .map_err(|_| SimpleError::new(
+            format!("--coverage argument not a number: {}",
+                    args.value_of("coverage").unwrap())))?;
+
+    let seed = args.value_of("seed").unwrap().parse::<u64>()
+        .map_err(|_| SimpleError::new(
+            format!("--seed argument not an integer: {}",
+                    args.value_of("seed").unwrap())))?;
+
+    let gzip_output = args.is_present("compress-output");
+
+    match args.value_of("input-unpaired") {
+        Some(input_filename) => {
+            let total_bases = match args.value_of("total-bases") {
+                Some(b) => b.parse::<u64>().map_err(|_| SimpleError::new(
+                    format!("--total-bases argument not an integer: {}", b)))?,
+                None => count_total_bases(input_filename)?,
+            };
+            let p = keep_probability(total_bases, genome_size, coverage)?;
+
+            let output_filename = args.value_of("output")
+                .ok_or(SimpleError::new(
+                    "--output is required".to_string()))?;
+
+            let kept = subsample(input_filename, output_filename, p, seed,
+                                 gzip_output)?;
+            println!("kept {} reads (p = {:.4})", kept, p);
+        }
+        None => {
+            let input_filenames: Vec<&str> = args
+                .values_of("input-paired-end").unwrap().collect();
+            let output_filenames: Vec<&str> = args
+                .values_of("output").unwrap().collect();
+            if output_filenames.len() != 2 {
+                return Err(SimpleError::new(
+                    "--output must give two files, one per mate, when \
+                    used with --input-paired-end".to_string()));
+            }
+
+            let total_bases = match args.value_of("total-bases") {
+                Some(b) => b.parse::<u64>().map_err(|_| SimpleError::new(
+                    format!("--total-bases argument not an integer: {}", b)))?,
+                None => count_total_bases(input_filenames[0])?
+                    + count_total_bases(input_filenames[1])?,
+            };
+            let p = keep_probability(total_bases, genome_size, coverage)?;
+
+            let kept = subsample_paired(input_filenames[0], input_filenames[1],
+                                        output_filenames[0], output_filenames[1],
+                                        p, seed, gzip_output)?;
+            println!("kept {} pairs (p = {:.4})", kept, p);
+        }
+    }
+
+    Ok(())
+}
+
+fn main() {
+    if let Err(e) = run() {
+        println!("fatal error: {}", e);
+        process::exit(1);
+    }
+}
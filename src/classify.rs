@@ -2,46 +2,203 @@ extern crate flate2;
 
 use kmer;
 use seq;
-use std::{result, error, fmt, cmp};
+use std::{result, error, fmt, cmp, thread};
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
-use std::io::{Write, BufWriter};
+use std::io::{Read, Write, BufWriter};
+use std::sync::{Arc, Mutex, mpsc};
 use self::flate2::Compression;
 use self::flate2::write::GzEncoder;
 
 type Result<T> = result::Result<T, Box<dyn error::Error>>;
 
+/// Concrete, `Send`-able error carried across the reader/counter threads'
+/// channels and join handles. `Box<dyn error::Error>` isn't `Send`, so it
+/// can't cross those boundaries directly; this just stores the message.
+#[derive(Debug)]
+pub struct ClassifyError {
+    message: String,
+}
+
+impl ClassifyError {
+    fn new(message: String) -> ClassifyError {
+        ClassifyError { message: message }
+    }
+}
+
+impl fmt::Display for ClassifyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl error::Error for ClassifyError {}
+
+// number of records batched together into a single job sent to the counter
+// pool, so that channel synchronization overhead is amortized across many
+// reads instead of paid once per read
+const CHUNK_SIZE: usize = 1000;
+
+// number of chunks that can be queued up between the reader and the counter
+// pool at once
+const CHUNK_BUFFER_SIZE: usize = 4;
+
+/// Maps a base to its 2-bit encoding (`A=00, C=01, G=10, T=11`), matching the
+/// encoding [kmer::kmer_to_bits] uses. Returns `None` for anything else, e.g.
+/// an ambiguity code like `N`.
+fn base_to_bits(base: u8) -> Option<u64> {
+    match base {
+        b'A' => Some(0b00),
+        b'C' => Some(0b01),
+        b'G' => Some(0b10),
+        b'T' => Some(0b11),
+        _ => None,
+    }
+}
+
 /// Count the kmers from each of two haplotypes that are present in a read.
 ///
+/// Instead of re-encoding each of the `L-k+1` windows from scratch (which is
+/// O(k) per window), this slides a 2-bit-packed window across the sequence
+/// incrementally: `fwd` is updated as `fwd = (fwd >> 2) | (bits << (2*(k-1)))`
+/// and the reverse complement `rev` as `rev = ((rev << 2) | comp_bits) & mask`,
+/// so each new base costs O(1). The canonical representation for lookup is
+/// `min(fwd, rev)`, matching [kmer::get_canonical_repr]'s semantics. Hitting a
+/// non-ACGT base resets both accumulators and restarts the window, since
+/// [kmer::kmer_to_bits] would have errored on it anyway.
+///
+/// If `min_base_qual` is `Some(q)` and `read` carries per-base qualities (i.e.
+/// came from a fastq or bam file), a window is only counted if every base
+/// quality in it is `>= q`; fasta reads have no qualities, so they're always
+/// counted regardless of this setting.
+///
 /// # Arguments
 /// * `hap_a_kmers`: a set of k-mers unique to haplotype A
 /// * `hap_b_kmers`: a set of k-mers unique to haplotype B
 /// * `read`: a [seq::SeqRecord] containing the sequence to analyze
 /// * `k`: the k-mer size
+/// * `min_base_qual`: minimum Phred quality required of every base in a
+///   window for it to be counted
 ///
-/// Returns a tuple `(hap_a_count, hap_b_count)` containing the number of k-mers
-/// from `hap_a_kmers` and `hap_b_kmers`, respectively, appearing in `read`.
-fn count_kmers_in_read(hap_a_kmers: &kmer::KmerSet, hap_b_kmers: &kmer::KmerSet,
-                       read: &seq::SeqRecord, k: usize) -> Result<(u32, u32)> {
+/// Returns a tuple `(hap_a_count, hap_b_count, skipped_for_quality)`: the
+/// number of k-mers from `hap_a_kmers` and `hap_b_kmers`, respectively,
+/// appearing in `read`, and the number of windows that were skipped because
+/// they failed the quality filter.
+pub(crate) fn count_kmers_in_read(hap_a_kmers: &kmer::KmerSet, hap_b_kmers: &kmer::KmerSet,
+                       read: &seq::SeqRecord, k: usize,
+                       min_base_qual: Option<u8>) -> Result<(u32, u32, u32)> {
+
+    let mut hap_a_count: u32 = 0;
+    let mut hap_b_count: u32 = 0;
+    let mut skipped_for_quality: u32 = 0;
+
+    let mask: u64 = if k >= 32 { u64::max_value() } else { (1u64 << (2 * k)) - 1 };
+
+    let qual_bytes = read.qual.as_ref().map(|q| q.as_bytes());
+
+    let mut fwd: u64 = 0;
+    let mut rev: u64 = 0;
+    let mut window_len: usize = 0; // number of valid bases accumulated so far
+
+    for (index, base) in read.seq.bytes().enumerate() {
+        let bits = match base_to_bits(base) {
+            Some(bits) => bits,
+            None => {
+                // ambiguous base: the k-mer stream breaks here, so restart
+                // the window instead of aborting the whole read
+                fwd = 0;
+                rev = 0;
+                window_len = 0;
+                continue;
+            },
+        };
+
+        fwd = (fwd >> 2) | (bits << (2 * (k - 1)));
+        rev = ((rev << 2) | (3 - bits)) & mask;
+        window_len = cmp::min(window_len + 1, k);
+
+        if window_len == k {
+            let passes_quality = match (min_base_qual, qual_bytes) {
+                (Some(min_q), Some(qual)) => qual[(index + 1 - k)..=index]
+                    .iter().all(|&q| q.saturating_sub(33) >= min_q),
+                _ => true,
+            };
+
+            if !passes_quality {
+                skipped_for_quality += 1;
+                continue;
+            }
+
+            let canonical = cmp::min(fwd, rev);
+
+            if hap_a_kmers.contains(&canonical) {
+                hap_a_count += 1;
+            }
+            // hap_a_kmers and hap_b_kmers *should* be mutually exclusive sets, so
+            // it shouldn't matter whether this is if or else if, but I'm doing it
+            // this way so that the answer is still correct even if they aren't.
+            if hap_b_kmers.contains(&canonical) {
+                hap_b_count += 1;
+            }
+        }
+    }
+
+    Ok((hap_a_count, hap_b_count, skipped_for_quality))
+}
+
+/// Like [count_kmers_in_read], but generic over [kmer::KmerEncoding] so it
+/// isn't limited to k <= 32. Each window is re-encoded from scratch via
+/// [kmer::canonical_kmer] instead of sliding a 2-bit-packed accumulator,
+/// since `KmerEncoding` doesn't expose the bit operations an incremental
+/// update would need; wide k-mers are expected to be a comparatively rare,
+/// already-slow path, so this isn't optimized the way the k <= 32 path is.
+pub(crate) fn count_kmers_in_read_wide<E: kmer::KmerEncoding>(
+    hap_a_kmers: &HashSet<E>, hap_b_kmers: &HashSet<E>,
+    read: &seq::SeqRecord, k: usize,
+    min_base_qual: Option<u8>) -> Result<(u32, u32, u32)> {
 
     let mut hap_a_count: u32 = 0;
     let mut hap_b_count: u32 = 0;
+    let mut skipped_for_quality: u32 = 0;
+
+    let seq_bytes = read.seq.as_bytes();
+    if seq_bytes.len() < k {
+        return Ok((0, 0, 0));
+    }
+
+    let qual_bytes = read.qual.as_ref().map(|q| q.as_bytes());
 
-    for i in 0..(read.seq.len() - k + 1) {
-        let bits = kmer::get_canonical_repr(&read.seq[i..i+k])
-            .and_then(|k| kmer::kmer_to_bits(&k))?;
+    for start in 0..=(seq_bytes.len() - k) {
+        let window = &seq_bytes[start..start + k];
+        if !window.iter().all(|&b|
+            b == b'A' || b == b'C' || b == b'G' || b == b'T') {
+            // ambiguous base somewhere in this window; skip it, matching
+            // count_kmers_in_read's treatment of non-ACGT bases
+            continue;
+        }
 
-        if hap_a_kmers.contains(&bits) {
+        let passes_quality = match (min_base_qual, &qual_bytes) {
+            (Some(min_q), Some(qual)) => qual[start..start + k]
+                .iter().all(|&q| q.saturating_sub(33) >= min_q),
+            _ => true,
+        };
+
+        if !passes_quality {
+            skipped_for_quality += 1;
+            continue;
+        }
+
+        let canonical: E = kmer::canonical_kmer(&read.seq[start..start + k])?;
+
+        if hap_a_kmers.contains(&canonical) {
             hap_a_count += 1;
         }
-        // hap_a_kmers and hap_b_kmers *should* be mutually exclusive sets, so
-        // it shouldn't matter whether this is if or else if, but I'm doing it
-        // this way so that the answer is still correct even if they aren't.
-        if hap_b_kmers.contains(&bits) {
+        if hap_b_kmers.contains(&canonical) {
             hap_b_count += 1;
         }
     }
 
-    Ok((hap_a_count, hap_b_count))
+    Ok((hap_a_count, hap_b_count, skipped_for_quality))
 }
 
 /// Look at the sizes of the k-mer sets and use these to calculating scaling
@@ -62,14 +219,139 @@ pub fn calc_scaling_factors(hap_a_kmers: &kmer::KmerSet,
     (scaling_factor_a, scaling_factor_b)
 }
 
+// assumed probability that a single counted k-mer match correctly reflects
+// the read's true haplotype, as opposed to being a coincidental match or the
+// product of a sequencing/assembly error; used as the per-observation
+// likelihood in calc_llr's log-likelihood ratio. Kept away from 0 and 1 so
+// that its logarithm is always finite.
+const KMER_MATCH_PROB: f32 = 0.99;
+
+/// Computes a log-likelihood ratio comparing the evidence for haplotype A
+/// against haplotype B, treating `hap_a_score` and `hap_b_score` (the scaled
+/// k-mer counts from [calc_scaling_factors]) as counts of independent,
+/// correct-with-probability-[KMER_MATCH_PROB] observations favoring each
+/// haplotype:
+///
+/// ```ignore
+/// LLR = a*log(p) + b*log(1-p) - (a*log(1-p) + b*log(p))
+///     = (a - b) * log(p / (1 - p))
+/// ```
+///
+/// Positive values favor haplotype A, negative values favor haplotype B, and
+/// a value of zero means the scores are tied. The magnitude grows with the
+/// size of the gap between `hap_a_score` and `hap_b_score`, so callers can
+/// compare it against a threshold to decide how much of a gap counts as
+/// confident.
+fn calc_llr(hap_a_score: f32, hap_b_score: f32) -> f32 {
+    (hap_a_score - hap_b_score) * (KMER_MATCH_PROB / (1.0 - KMER_MATCH_PROB)).ln()
+}
+
 // type alias for something that implements Write. We need this because some of
 // the code in this file opens up either a GzipEncoder or a File and then uses
-// them the same way downstream.
-type BoxWrite = Box<dyn Write>;
+// them the same way downstream. It has to be Send so that the writer half of
+// classify_unpaired's threaded pipeline can own one on its own thread.
+type BoxWrite = Box<dyn Write + Send>;
+
+// a batch of records read off disk together, tagged with its position in the
+// input stream so the writer can put chunks back in order even though the
+// counter pool may finish them out of order
+struct RecordChunk {
+    index: usize,
+    records: Vec<seq::SeqRecord>,
+}
+
+// the per-record classification counts for a chunk, still tagged with its
+// position in the input stream
+struct CountedChunk {
+    index: usize,
+    records: Vec<(seq::SeqRecord, u32, u32, u32)>,
+}
+
+/// Read chunks of `CHUNK_SIZE` records off `input_reader` and send them down
+/// `chunk_sender` for the counter pool to work on. Runs on its own thread so
+/// disk I/O and decompression never block the CPU-bound counters.
+///
+/// If a record fails to parse, reading stops right there and the error is
+/// returned from the joined thread instead of panicking; whatever chunks
+/// were already sent are still counted and written out normally.
+fn spawn_chunk_reader<T>(input_reader: seq::SeqReader<T>,
+                         chunk_sender: mpsc::SyncSender<RecordChunk>)
+    -> thread::JoinHandle<result::Result<(), ClassifyError>>
+    where T: Read + Send + 'static {
+    thread::spawn(move || {
+        let mut index = 0;
+        let mut records = Vec::with_capacity(CHUNK_SIZE);
+        for result in input_reader {
+            let record = result.map_err(|e| ClassifyError::new(
+                format!("error reading input: {}", e)))?;
+            records.push(record);
+            if records.len() == CHUNK_SIZE {
+                let chunk = RecordChunk { index, records };
+                // the counter pool may already be gone if a counter hit an
+                // error of its own; nothing more we can do at that point
+                if chunk_sender.send(chunk).is_err() {
+                    return Ok(());
+                }
+                index += 1;
+                records = Vec::with_capacity(CHUNK_SIZE);
+            }
+        }
+        if !records.is_empty() {
+            let _ = chunk_sender.send(RecordChunk { index, records });
+        }
+        Ok(())
+    })
+}
+
+/// Pull chunks of records off `chunk_receiver`, count hap-A/hap-B kmers in
+/// each one, and send the results down `counted_sender`. One of these runs
+/// per counter thread; `chunk_receiver` is shared by the whole pool.
+///
+/// If counting a record fails, this thread stops and returns the error from
+/// its joined handle instead of panicking; the other counters in the pool
+/// keep draining `chunk_receiver` and the writer keeps draining whatever
+/// gets sent down `counted_sender`.
+fn spawn_counter(hap_a_kmers: Arc<kmer::KmerSet>,
+                 hap_b_kmers: Arc<kmer::KmerSet>,
+                 chunk_receiver: Arc<Mutex<mpsc::Receiver<RecordChunk>>>,
+                 counted_sender: mpsc::Sender<CountedChunk>,
+                 k: usize,
+                 min_base_qual: Option<u8>)
+    -> thread::JoinHandle<result::Result<(), ClassifyError>> {
+    thread::spawn(move || {
+        loop {
+            let chunk = match chunk_receiver.lock().unwrap().recv() {
+                Ok(chunk) => chunk,
+                Err(_) => break, // reader is done and channel is empty
+            };
+
+            let mut records = Vec::with_capacity(chunk.records.len());
+            for record in chunk.records {
+                let (hap_a_count, hap_b_count, skipped) = count_kmers_in_read(
+                    &hap_a_kmers, &hap_b_kmers, &record, k, min_base_qual)
+                    .map_err(|e| ClassifyError::new(
+                        format!("error counting k-mers: {}", e)))?;
+                records.push((record, hap_a_count, hap_b_count, skipped));
+            }
+
+            // the writer may already be gone if a previous chunk failed to
+            // write; nothing more we can do at that point
+            let _ = counted_sender.send(CountedChunk { index: chunk.index, records });
+        }
+        Ok(())
+    })
+}
 
 /// Classify all the reads in a fasta/q file into one of two haplotypes, or as
 /// an unknown haplotype, based on the kmer composition.
 ///
+/// Reading, kmer counting, and writing all happen concurrently: a dedicated
+/// reader thread hands off fixed-size chunks of records to a pool of counter
+/// threads over a bounded channel, and this thread drains the counted chunks
+/// in input order and writes them out, so output ordering and the TSV log
+/// stay identical to the single-threaded result no matter how many threads
+/// are used.
+///
 /// # Arguments
 /// * `hap_a_kmers`: the set of all kmers unique to haplotype A, in bits
 /// * `hap_b_kmers`: the set of all kmers unique to haplotype B, in bits
@@ -79,18 +361,30 @@ type BoxWrite = Box<dyn Write>;
 /// * `hap_b_out_prefix`: prefix for path where output file(s) for hapB will go
 /// * `hap_u_out_prefix`: prefix for path where output file(s) for hapU will go
 /// * `k`: the k-mer size
+/// * `num_threads`: total number of threads to use, including the reader and
+///   this thread; at least one thread is always reserved for counting
+/// * `min_base_qual`: if set, a k-mer window is only counted for fastq/bam
+///   reads when every base quality in it meets this Phred cutoff
+/// * `confidence`: a read is only classified to haplotype A or B if the
+///   magnitude of [calc_llr]'s log-likelihood ratio exceeds this threshold;
+///   otherwise it's called U. `0.0` reproduces the simple majority-wins rule.
 ///
 /// # Errors
 /// * [io::Error]: if any input or output file can't be opened
 /// * [seq::ExtensionError]: if the input file type cannot be determined
-pub fn classify_unpaired(hap_a_kmers: &kmer::KmerSet,
-                         hap_b_kmers: &kmer::KmerSet,
+/// * [ClassifyError]: if a record in the input can't be parsed, or a window
+///   of it can't be counted
+pub fn classify_unpaired(hap_a_kmers: kmer::KmerSet,
+                         hap_b_kmers: kmer::KmerSet,
                          input_reads_filename: &str,
                          hap_a_out_prefix: &str,
                          hap_b_out_prefix: &str,
                          hap_u_out_prefix: &str,
                          gzip_output: bool,
-                         k: usize) -> Result<()> {
+                         k: usize,
+                         num_threads: usize,
+                         min_base_qual: Option<u8>,
+                         confidence: f32) -> Result<()> {
 
     // set up input stream
     // this can return io::Error or seq::ExtensionError
@@ -111,28 +405,66 @@ pub fn classify_unpaired(hap_a_kmers: &kmer::KmerSet,
 
     // calculate read-count scaling factors
     let (scaling_factor_a, scaling_factor_b) =
-        calc_scaling_factors(hap_a_kmers, hap_b_kmers);
+        calc_scaling_factors(&hap_a_kmers, &hap_b_kmers);
 
-    for result in input_reader {
-        let record = result?;
-        let (hap_a_count, hap_b_count) = count_kmers_in_read(
-            hap_a_kmers, hap_b_kmers, &record, k)?;
-        let hap_a_score = (hap_a_count as f32) * scaling_factor_a;
-        let hap_b_score = (hap_b_count as f32) * scaling_factor_b;
+    let hap_a_kmers = Arc::new(hap_a_kmers);
+    let hap_b_kmers = Arc::new(hap_b_kmers);
 
-        let mut haplotype = "?";
-        if hap_a_score > hap_b_score {
-            hap_a_out.write(record.entry_string.as_bytes())?;
-            haplotype = "A";
-        } else if hap_b_score > hap_a_score {
-            hap_b_out.write(record.entry_string.as_bytes())?;
-            haplotype = "B";
-        } else {
-            hap_u_out.write(record.entry_string.as_bytes())?;
-            haplotype = "U";
+    // reserve one thread each for reading and writing; whatever's left goes
+    // to the counter pool, with a floor of one counter
+    let num_counters = cmp::max(num_threads.saturating_sub(2), 1);
+
+    let (chunk_sender, chunk_receiver) =
+        mpsc::sync_channel(CHUNK_BUFFER_SIZE);
+    let (counted_sender, counted_receiver) = mpsc::channel();
+
+    let reader = spawn_chunk_reader(input_reader, chunk_sender);
+
+    let chunk_receiver = Arc::new(Mutex::new(chunk_receiver));
+    let mut counters = Vec::with_capacity(num_counters);
+    for _ in 0..num_counters {
+        counters.push(spawn_counter(Arc::clone(&hap_a_kmers),
+                                    Arc::clone(&hap_b_kmers),
+                                    Arc::clone(&chunk_receiver),
+                                    counted_sender.clone(), k, min_base_qual));
+    }
+    // drop our own sender so the channel closes once every counter is done
+    drop(counted_sender);
+
+    // counted chunks can arrive out of order, so buffer the ones that are
+    // ahead of schedule until it's their turn to be written
+    let mut pending: HashMap<usize, CountedChunk> = HashMap::new();
+    let mut next_index = 0;
+
+    while let Ok(chunk) = counted_receiver.recv() {
+        pending.insert(chunk.index, chunk);
+        while let Some(chunk) = pending.remove(&next_index) {
+            for (record, hap_a_count, hap_b_count, skipped) in chunk.records {
+                let hap_a_score = (hap_a_count as f32) * scaling_factor_a;
+                let hap_b_score = (hap_b_count as f32) * scaling_factor_b;
+                let llr = calc_llr(hap_a_score, hap_b_score);
+
+                let haplotype;
+                if llr > confidence {
+                    hap_a_out.write(record.entry_string.as_bytes())?;
+                    haplotype = "A";
+                } else if llr < -confidence {
+                    hap_b_out.write(record.entry_string.as_bytes())?;
+                    haplotype = "B";
+                } else {
+                    hap_u_out.write(record.entry_string.as_bytes())?;
+                    haplotype = "U";
+                }
+                println!("{}\t{}\t{}\t{}\t{}\t{}", record.id, haplotype,
+                         hap_a_score, hap_b_score, llr, skipped);
+            }
+            next_index += 1;
         }
-        println!("{}\t{}\t{}\t{}", record.id, haplotype,
-                 hap_a_score, hap_b_score);
+    }
+
+    reader.join().expect("reader thread panicked")?;
+    for counter in counters {
+        counter.join().expect("counter thread panicked")?;
     }
 
     Ok(())
@@ -159,15 +491,242 @@ fn open_writer(prefix: &str, extension: &str, gzip: bool) -> Result<BoxWrite> {
     }
 }
 
+/// Classify paired-end reads into one of two haplotypes, or as an unknown
+/// haplotype, based on the combined kmer composition of both mates. A pair is
+/// always kept together: both mates are written to whichever haplotype's
+/// output the pair as a whole is assigned to.
+///
+/// # Arguments
+/// * `hap_a_kmers`: the set of all kmers unique to haplotype A, in bits
+/// * `hap_b_kmers`: the set of all kmers unique to haplotype B, in bits
+/// * `input_reads_filename_a`: path to the file containing mate 1 of each pair
+/// * `input_reads_filename_b`: path to the file containing mate 2 of each pair
+/// * `hap_a_out_prefix`: prefix for path where output file(s) for hapA will go
+/// * `hap_b_out_prefix`: prefix for path where output file(s) for hapB will go
+/// * `hap_u_out_prefix`: prefix for path where output file(s) for hapU will go
+/// * `k`: the k-mer size
+/// * `min_base_qual`: if set, a k-mer window is only counted for fastq/bam
+///   reads when every base quality in it meets this Phred cutoff
+/// * `confidence`: a pair is only classified to haplotype A or B if the
+///   magnitude of [calc_llr]'s log-likelihood ratio exceeds this threshold;
+///   otherwise it's called U. `0.0` reproduces the simple majority-wins rule.
+///
+/// # Errors
+/// * [io::Error]: if any input or output file can't be opened
+/// * [seq::ExtensionError]: if the input file type cannot be determined
 pub fn classify_paired(hap_a_kmers: &kmer::KmerSet, hap_b_kmers: &kmer::KmerSet,
                    input_reads_filename_a: &str, input_reads_filename_b: &str,
-                   hap_a_out_prefix: &str, hap_b_output_prefix: &str,
-                   hap_u_out_prefix: &str) -> Result<()> {
+                   hap_a_out_prefix: &str, hap_b_out_prefix: &str,
+                   hap_u_out_prefix: &str,
+                   gzip_output: bool,
+                   k: usize,
+                   min_base_qual: Option<u8>,
+                   confidence: f32) -> Result<()> {
+
+    // set up input streams, one for each mate
+    let input_reader_a = seq::SeqReader::from_path(input_reads_filename_a)?;
+    let input_reader_b = seq::SeqReader::from_path(input_reads_filename_b)?;
+
+    // figure out correct extension for output files based on input_reader_a's
+    // type; both mates are expected to be the same format
+    let extension = match input_reader_a {
+        seq::SeqReader::Fasta(_) => ".fa",
+        // write bam entries as fastq because there's really no good reason to
+        // output unaligned reads as bam.
+        seq::SeqReader::Fastq(_) | seq::SeqReader::Bam(_,_) => ".fq",
+    };
+
+    // set up output streams: one pair of R1/R2 writers per haplotype, so
+    // mates are never split across files
+    let mut hap_a_out_1 = open_writer(
+        &(hap_a_out_prefix.to_owned() + "_R1"), extension, gzip_output)?;
+    let mut hap_a_out_2 = open_writer(
+        &(hap_a_out_prefix.to_owned() + "_R2"), extension, gzip_output)?;
+    let mut hap_b_out_1 = open_writer(
+        &(hap_b_out_prefix.to_owned() + "_R1"), extension, gzip_output)?;
+    let mut hap_b_out_2 = open_writer(
+        &(hap_b_out_prefix.to_owned() + "_R2"), extension, gzip_output)?;
+    let mut hap_u_out_1 = open_writer(
+        &(hap_u_out_prefix.to_owned() + "_R1"), extension, gzip_output)?;
+    let mut hap_u_out_2 = open_writer(
+        &(hap_u_out_prefix.to_owned() + "_R2"), extension, gzip_output)?;
 
     // calculate read-count scaling factors
     let (scaling_factor_a, scaling_factor_b) =
         calc_scaling_factors(hap_a_kmers, hap_b_kmers);
-    unimplemented!()
+
+    for (result_a, result_b) in input_reader_a.zip(input_reader_b) {
+        let record_a = result_a?;
+        let record_b = result_b?;
+
+        // sum the kmer counts from both mates before scoring the pair
+        let (hap_a_count_1, hap_b_count_1, skipped_1) = count_kmers_in_read(
+            hap_a_kmers, hap_b_kmers, &record_a, k, min_base_qual)?;
+        let (hap_a_count_2, hap_b_count_2, skipped_2) = count_kmers_in_read(
+            hap_a_kmers, hap_b_kmers, &record_b, k, min_base_qual)?;
+        let hap_a_score = ((hap_a_count_1 + hap_a_count_2) as f32)
+            * scaling_factor_a;
+        let hap_b_score = ((hap_b_count_1 + hap_b_count_2) as f32)
+            * scaling_factor_b;
+
+        let llr = calc_llr(hap_a_score, hap_b_score);
+
+        let haplotype;
+        if llr > confidence {
+            hap_a_out_1.write(record_a.entry_string.as_bytes())?;
+            hap_a_out_2.write(record_b.entry_string.as_bytes())?;
+            haplotype = "A";
+        } else if llr < -confidence {
+            hap_b_out_1.write(record_a.entry_string.as_bytes())?;
+            hap_b_out_2.write(record_b.entry_string.as_bytes())?;
+            haplotype = "B";
+        } else {
+            hap_u_out_1.write(record_a.entry_string.as_bytes())?;
+            hap_u_out_2.write(record_b.entry_string.as_bytes())?;
+            haplotype = "U";
+        }
+        println!("{}\t{}\t{}\t{}\t{}\t{}", record_a.id, haplotype,
+                 hap_a_score, hap_b_score, llr, skipped_1 + skipped_2);
+    }
+
+    Ok(())
+}
+
+/// Like [classify_unpaired], but generic over [kmer::KmerEncoding] so k-mers
+/// wider than 32 bases (loaded via [kmer::read_kmers_into_any_set]) can
+/// actually be classified, not just parsed. Single-threaded and un-batched,
+/// unlike [classify_unpaired]'s reader/counter-pool pipeline: wide k-mers are
+/// an uncommon path, and [count_kmers_in_read_wide] is already the slow part.
+///
+/// # Arguments
+/// See [classify_unpaired]; `hap_a_kmers`/`hap_b_kmers` hold `E`-encoded
+/// k-mers instead of the `u64`-only [kmer::KmerSet].
+///
+/// # Errors
+/// * [io::Error]: if any input or output file can't be opened
+/// * [seq::ExtensionError]: if the input file type cannot be determined
+pub fn classify_unpaired_wide<E: kmer::KmerEncoding>(
+    hap_a_kmers: HashSet<E>, hap_b_kmers: HashSet<E>,
+    input_reads_filename: &str,
+    hap_a_out_prefix: &str, hap_b_out_prefix: &str, hap_u_out_prefix: &str,
+    gzip_output: bool, k: usize, min_base_qual: Option<u8>,
+    confidence: f32) -> Result<()> {
+
+    let input_reader = seq::SeqReader::from_path(input_reads_filename)?;
+
+    let extension = match input_reader {
+        seq::SeqReader::Fasta(_) => ".fa",
+        seq::SeqReader::Fastq(_) | seq::SeqReader::Bam(_,_) => ".fq",
+    };
+
+    let mut hap_a_out = open_writer(hap_a_out_prefix, extension, gzip_output)?;
+    let mut hap_b_out = open_writer(hap_b_out_prefix, extension, gzip_output)?;
+    let mut hap_u_out = open_writer(hap_u_out_prefix, extension, gzip_output)?;
+
+    let max_num_kmers = cmp::max(hap_a_kmers.len(), hap_b_kmers.len());
+    let scaling_factor_a = (max_num_kmers as f32) / (hap_a_kmers.len() as f32);
+    let scaling_factor_b = (max_num_kmers as f32) / (hap_b_kmers.len() as f32);
+
+    for result in input_reader {
+        let record = result?;
+
+        let (hap_a_count, hap_b_count, skipped) = count_kmers_in_read_wide(
+            &hap_a_kmers, &hap_b_kmers, &record, k, min_base_qual)?;
+        let hap_a_score = (hap_a_count as f32) * scaling_factor_a;
+        let hap_b_score = (hap_b_count as f32) * scaling_factor_b;
+        let llr = calc_llr(hap_a_score, hap_b_score);
+
+        let haplotype;
+        if llr > confidence {
+            hap_a_out.write(record.entry_string.as_bytes())?;
+            haplotype = "A";
+        } else if llr < -confidence {
+            hap_b_out.write(record.entry_string.as_bytes())?;
+            haplotype = "B";
+        } else {
+            hap_u_out.write(record.entry_string.as_bytes())?;
+            haplotype = "U";
+        }
+        println!("{}\t{}\t{}\t{}\t{}\t{}", record.id, haplotype,
+                 hap_a_score, hap_b_score, llr, skipped);
+    }
+
+    Ok(())
+}
+
+/// Like [classify_paired], but generic over [kmer::KmerEncoding] so k-mers
+/// wider than 32 bases can be classified; [classify_paired] only accepts the
+/// `u64`-only [kmer::KmerSet]. Like [classify_paired], it runs on one thread.
+///
+/// # Errors
+/// * [io::Error]: if any input or output file can't be opened
+/// * [seq::ExtensionError]: if the input file type cannot be determined
+pub fn classify_paired_wide<E: kmer::KmerEncoding>(
+    hap_a_kmers: &HashSet<E>, hap_b_kmers: &HashSet<E>,
+    input_reads_filename_a: &str, input_reads_filename_b: &str,
+    hap_a_out_prefix: &str, hap_b_out_prefix: &str, hap_u_out_prefix: &str,
+    gzip_output: bool, k: usize, min_base_qual: Option<u8>,
+    confidence: f32) -> Result<()> {
+
+    let input_reader_a = seq::SeqReader::from_path(input_reads_filename_a)?;
+    let input_reader_b = seq::SeqReader::from_path(input_reads_filename_b)?;
+
+    let extension = match input_reader_a {
+        seq::SeqReader::Fasta(_) => ".fa",
+        seq::SeqReader::Fastq(_) | seq::SeqReader::Bam(_,_) => ".fq",
+    };
+
+    let mut hap_a_out_1 = open_writer(
+        &(hap_a_out_prefix.to_owned() + "_R1"), extension, gzip_output)?;
+    let mut hap_a_out_2 = open_writer(
+        &(hap_a_out_prefix.to_owned() + "_R2"), extension, gzip_output)?;
+    let mut hap_b_out_1 = open_writer(
+        &(hap_b_out_prefix.to_owned() + "_R1"), extension, gzip_output)?;
+    let mut hap_b_out_2 = open_writer(
+        &(hap_b_out_prefix.to_owned() + "_R2"), extension, gzip_output)?;
+    let mut hap_u_out_1 = open_writer(
+        &(hap_u_out_prefix.to_owned() + "_R1"), extension, gzip_output)?;
+    let mut hap_u_out_2 = open_writer(
+        &(hap_u_out_prefix.to_owned() + "_R2"), extension, gzip_output)?;
+
+    let max_num_kmers = cmp::max(hap_a_kmers.len(), hap_b_kmers.len());
+    let scaling_factor_a = (max_num_kmers as f32) / (hap_a_kmers.len() as f32);
+    let scaling_factor_b = (max_num_kmers as f32) / (hap_b_kmers.len() as f32);
+
+    for (result_a, result_b) in input_reader_a.zip(input_reader_b) {
+        let record_a = result_a?;
+        let record_b = result_b?;
+
+        let (hap_a_count_1, hap_b_count_1, skipped_1) = count_kmers_in_read_wide(
+            hap_a_kmers, hap_b_kmers, &record_a, k, min_base_qual)?;
+        let (hap_a_count_2, hap_b_count_2, skipped_2) = count_kmers_in_read_wide(
+            hap_a_kmers, hap_b_kmers, &record_b, k, min_base_qual)?;
+        let hap_a_score = ((hap_a_count_1 + hap_a_count_2) as f32)
+            * scaling_factor_a;
+        let hap_b_score = ((hap_b_count_1 + hap_b_count_2) as f32)
+            * scaling_factor_b;
+
+        let llr = calc_llr(hap_a_score, hap_b_score);
+
+        let haplotype;
+        if llr > confidence {
+            hap_a_out_1.write(record_a.entry_string.as_bytes())?;
+            hap_a_out_2.write(record_b.entry_string.as_bytes())?;
+            haplotype = "A";
+        } else if llr < -confidence {
+            hap_b_out_1.write(record_a.entry_string.as_bytes())?;
+            hap_b_out_2.write(record_b.entry_string.as_bytes())?;
+            haplotype = "B";
+        } else {
+            hap_u_out_1.write(record_a.entry_string.as_bytes())?;
+            hap_u_out_2.write(record_b.entry_string.as_bytes())?;
+            haplotype = "U";
+        }
+        println!("{}\t{}\t{}\t{}\t{}\t{}", record_a.id, haplotype,
+                 hap_a_score, hap_b_score, llr, skipped_1 + skipped_2);
+    }
+
+    Ok(())
 }
 
 #[cfg(test)]
@@ -180,6 +739,7 @@ mod tests {
             id: "test".to_string(),
             seq: "AACAACGCGCGTCGGTATCT".to_string(),
             entry_string: ">test\nAACAACGCGCGTCGGTATCT".to_string(),
+            qual: None,
         };
 
         let hap_a_kmer_strings = ["AACAA", "AGATA", "TTTTT"];
@@ -191,10 +751,49 @@ mod tests {
         let hap_b_kmer_bits: kmer::KmerSet = hap_b_kmer_strings.iter()
             .map(|s| kmer::kmer_to_bits(s).unwrap()).collect();
 
-        let (hap_a_counts, hap_b_counts) =
-            count_kmers_in_read(&hap_a_kmer_bits, &hap_b_kmer_bits, &read, k)
-            .unwrap();
+        let (hap_a_counts, hap_b_counts, skipped) =
+            count_kmers_in_read(&hap_a_kmer_bits, &hap_b_kmer_bits, &read, k,
+                               None).unwrap();
         assert_eq!(hap_a_counts, 2);
         assert_eq!(hap_b_counts, 1);
+        assert_eq!(skipped, 0);
+    }
+
+    #[test]
+    fn test_count_skips_windows_failing_min_base_qual() {
+        // the single 5-base window covering this whole read would match
+        // hap_a_kmer_bits if counted, but its first base is Phred 0, well
+        // below the quality cutoff, so it should be skipped instead
+        let read = seq::SeqRecord {
+            id: "test".to_string(),
+            seq: "AACAA".to_string(),
+            entry_string: "@test\nAACAA\n+\n!IIII".to_string(),
+            qual: Some("!IIII".to_string()),
+        };
+
+        let hap_a_kmer_bits: kmer::KmerSet = ["AACAA"].iter()
+            .map(|s| kmer::kmer_to_bits(s).unwrap()).collect();
+        let hap_b_kmer_bits: kmer::KmerSet = kmer::KmerSet::new();
+        let k: usize = 5;
+
+        let (hap_a_counts, hap_b_counts, skipped) =
+            count_kmers_in_read(&hap_a_kmer_bits, &hap_b_kmer_bits, &read, k,
+                               Some(10)).unwrap();
+        assert_eq!(hap_a_counts, 0);
+        assert_eq!(hap_b_counts, 0);
+        assert_eq!(skipped, 1);
+    }
+
+    #[test]
+    fn calc_llr_favors_the_higher_scaled_score() {
+        // (3 - 1) * ln(0.99 / 0.01)
+        let llr = calc_llr(3.0, 1.0);
+        assert!((llr - 9.190238).abs() < 1e-4);
+
+        // swapping the scores negates the LLR, favoring haplotype B instead
+        assert_eq!(calc_llr(1.0, 3.0), -llr);
+
+        // tied scores are a wash
+        assert_eq!(calc_llr(2.0, 2.0), 0.0);
     }
 }
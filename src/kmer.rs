@@ -1,7 +1,7 @@
 use std::fmt;
 use std::collections::HashSet;
 use std::fs::File;
-use std::io::{self, BufReader, BufRead};
+use std::io::{self, BufReader, BufRead, Read};
 use std::error::Error;
 use std::{result, convert};
 
@@ -10,7 +10,8 @@ const MAX_KMER_LENGTH: usize = 32;
 #[derive(Debug)]
 pub enum KmerError {
     InvalidBaseError(char),
-    LengthError(usize),
+    /// (k-mer length, maximum length of the encoding that rejected it)
+    LengthError(usize, usize),
     Io(io::Error),
     BadKmerFile(usize),
 }
@@ -20,9 +21,9 @@ impl fmt::Display for KmerError {
         match self {
             KmerError::InvalidBaseError(base) =>
                 write!(f, "Invalid base character: '{}'", base),
-            KmerError::LengthError(length) =>
+            KmerError::LengthError(length, max) =>
                 write!(f, "k-mer length ({}) is greater than maximum ({})",
-                    length, MAX_KMER_LENGTH),
+                    length, max),
             KmerError::Io(e) => e.fmt(f),
             KmerError::BadKmerFile(l) =>
                 write!(f, "Cannot read k-mer on line {}", l + 1),
@@ -43,7 +44,7 @@ pub type KmerSet = HashSet<u64>;
 
 pub fn kmer_to_bits(kmer: &str) -> Result<u64> {
     if kmer.len() > MAX_KMER_LENGTH {
-        return Err(KmerError::LengthError(kmer.len()));
+        return Err(KmerError::LengthError(kmer.len(), MAX_KMER_LENGTH));
     }
 
     let mut bit_repr: u64 = 0;
@@ -65,7 +66,7 @@ pub fn kmer_to_bits(kmer: &str) -> Result<u64> {
 
 pub fn bits_to_kmer(bits: u64, k: usize) -> Result<String> {
     if k > MAX_KMER_LENGTH {
-        return Err(KmerError::LengthError(k));
+        return Err(KmerError::LengthError(k, MAX_KMER_LENGTH));
     }
 
     let mut string_repr = String::new();
@@ -108,6 +109,85 @@ pub fn get_canonical_repr(kmer: &str) -> Result<String> {
         kmer.to_string()} else {revcomp.to_string()})
 }
 
+fn base_to_bits(base: u8) -> Option<u64> {
+    match base {
+        b'A' => Some(0b00),
+        b'C' => Some(0b01),
+        b'G' => Some(0b10),
+        b'T' => Some(0b11),
+        _ => None,
+    }
+}
+
+/// Slides a 2-bit-packed window of length `k` across `sequence` and yields
+/// the canonical (`min(forward, revcomp)`) representation of each one, with
+/// no per-k-mer allocation and O(1) work per base instead of the O(k) that
+/// re-encoding each window from scratch via [kmer_to_bits] would cost.
+///
+/// The emitted values match [kmer_to_bits]'s bit layout (the first base of
+/// the window occupies the lowest 2 bits): `fwd` is updated as
+/// `fwd = (fwd >> 2) | (code(base) << (2*(k-1)))` and the reverse complement
+/// `rev` as `rev = ((rev << 2) | comp_code(base)) & mask`.
+///
+/// A non-ACGT base breaks the k-mer stream: both registers reset, and no
+/// value is emitted again until `k` valid bases have been re-accumulated.
+pub struct CanonicalKmerIter<'a> {
+    bytes: std::str::Bytes<'a>,
+    k: usize,
+    mask: u64,
+    fwd: u64,
+    rev: u64,
+    window_len: usize,
+}
+
+impl<'a> CanonicalKmerIter<'a> {
+    /// Creates a new iterator over the canonical k-mers of `sequence`.
+    pub fn new(sequence: &'a str, k: usize) -> Result<CanonicalKmerIter<'a>> {
+        if k > MAX_KMER_LENGTH {
+            return Err(KmerError::LengthError(k, MAX_KMER_LENGTH));
+        }
+
+        Ok(CanonicalKmerIter {
+            bytes: sequence.bytes(),
+            k: k,
+            mask: if k >= 32 { u64::max_value() } else { (1u64 << (2 * k)) - 1 },
+            fwd: 0,
+            rev: 0,
+            window_len: 0,
+        })
+    }
+}
+
+impl<'a> Iterator for CanonicalKmerIter<'a> {
+    type Item = u64;
+
+    fn next(&mut self) -> Option<u64> {
+        while let Some(base) = self.bytes.next() {
+            let bits = match base_to_bits(base) {
+                Some(bits) => bits,
+                None => {
+                    // ambiguous base: the k-mer stream breaks here, so
+                    // restart the window instead of aborting the sequence
+                    self.fwd = 0;
+                    self.rev = 0;
+                    self.window_len = 0;
+                    continue;
+                },
+            };
+
+            self.fwd = (self.fwd >> 2) | (bits << (2 * (self.k - 1)));
+            self.rev = ((self.rev << 2) | (3 - bits)) & self.mask;
+            self.window_len = std::cmp::min(self.window_len + 1, self.k);
+
+            if self.window_len == self.k {
+                return Some(std::cmp::min(self.fwd, self.rev));
+            }
+        }
+
+        None
+    }
+}
+
 pub fn get_kmer_size(file: File) -> Result<usize> {
     let mut buf = String::new();
     let mut reader = BufReader::new(file);
@@ -129,6 +209,198 @@ pub fn read_kmers_into_set(file: File) -> Result<KmerSet> {
     Ok(kmers)
 }
 
+const MAX_KMER_LENGTH_U128: usize = 64;
+const MAX_KMER_LENGTH_BIG: usize = 128;
+const BIG_KMER_WORDS: usize = 4;
+
+/// A k-mer bit-packing scheme that [KmerSet]-like `HashSet`s can be built
+/// out of, so callers aren't stuck with [kmer_to_bits]'s 32-base `u64` cap.
+/// Implemented for `u64` (k <= 32, the original encoding), `u128` (k <= 64),
+/// and [BigKmer] (k <= 128). `Ord` is required so [canonical_kmer] can pick
+/// `min(forward, revcomp)` the same way [get_canonical_repr] does for
+/// strings.
+pub trait KmerEncoding: Eq + std::hash::Hash + Copy + Ord {
+    /// the largest k this encoding can represent
+    fn max_length() -> usize;
+    fn encode(kmer: &str) -> Result<Self>;
+    fn decode(&self, k: usize) -> Result<String>;
+}
+
+impl KmerEncoding for u64 {
+    fn max_length() -> usize { MAX_KMER_LENGTH }
+    fn encode(kmer: &str) -> Result<u64> { kmer_to_bits(kmer) }
+    fn decode(&self, k: usize) -> Result<String> { bits_to_kmer(*self, k) }
+}
+
+impl KmerEncoding for u128 {
+    fn max_length() -> usize { MAX_KMER_LENGTH_U128 }
+
+    fn encode(kmer: &str) -> Result<u128> {
+        if kmer.len() > MAX_KMER_LENGTH_U128 {
+            return Err(KmerError::LengthError(kmer.len(), MAX_KMER_LENGTH_U128));
+        }
+
+        let mut bit_repr: u128 = 0;
+
+        for (index, base) in kmer.chars().enumerate() {
+            let this_base_bits: u128 = match base {
+                'A' => 0b00,
+                'C' => 0b01,
+                'G' => 0b10,
+                'T' => 0b11,
+                _ => return Err(KmerError::InvalidBaseError(base)),
+            };
+
+            bit_repr += this_base_bits << (index * 2);
+        }
+
+        Ok(bit_repr)
+    }
+
+    fn decode(&self, k: usize) -> Result<String> {
+        if k > MAX_KMER_LENGTH_U128 {
+            return Err(KmerError::LengthError(k, MAX_KMER_LENGTH_U128));
+        }
+
+        let mut string_repr = String::new();
+
+        for index in 0..k {
+            let this_base_bits = (self >> (index * 2)) & 0b11;
+            string_repr.push(match this_base_bits {
+                0b00 => 'A',
+                0b01 => 'C',
+                0b10 => 'G',
+                0b11 => 'T',
+                _ => panic!("Two bits cannot have value outside [0,3]."),
+            });
+        }
+
+        Ok(string_repr)
+    }
+}
+
+/// Packs a k-mer up to 128 bases long into four `u64` words (32 bases per
+/// word, least-significant word first), for k-mer sizes too large to fit in
+/// a `u128`.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct BigKmer([u64; BIG_KMER_WORDS]);
+
+// compares the most-significant word first, so a BigKmer orders the same way
+// a single integer holding all of its bits would, as u64 and u128 do
+impl Ord for BigKmer {
+    fn cmp(&self, other: &BigKmer) -> std::cmp::Ordering {
+        self.0.iter().rev().cmp(other.0.iter().rev())
+    }
+}
+
+impl PartialOrd for BigKmer {
+    fn partial_cmp(&self, other: &BigKmer) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl KmerEncoding for BigKmer {
+    fn max_length() -> usize { MAX_KMER_LENGTH_BIG }
+
+    fn encode(kmer: &str) -> Result<BigKmer> {
+        if kmer.len() > MAX_KMER_LENGTH_BIG {
+            return Err(KmerError::LengthError(kmer.len(), MAX_KMER_LENGTH_BIG));
+        }
+
+        let mut words = [0u64; BIG_KMER_WORDS];
+
+        for (index, base) in kmer.chars().enumerate() {
+            let this_base_bits: u64 = match base {
+                'A' => 0b00,
+                'C' => 0b01,
+                'G' => 0b10,
+                'T' => 0b11,
+                _ => return Err(KmerError::InvalidBaseError(base)),
+            };
+
+            words[index / 32] |= this_base_bits << ((index % 32) * 2);
+        }
+
+        Ok(BigKmer(words))
+    }
+
+    fn decode(&self, k: usize) -> Result<String> {
+        if k > MAX_KMER_LENGTH_BIG {
+            return Err(KmerError::LengthError(k, MAX_KMER_LENGTH_BIG));
+        }
+
+        let mut string_repr = String::new();
+
+        for index in 0..k {
+            let this_base_bits = (self.0[index / 32] >> ((index % 32) * 2)) & 0b11;
+            string_repr.push(match this_base_bits {
+                0b00 => 'A',
+                0b01 => 'C',
+                0b10 => 'G',
+                0b11 => 'T',
+                _ => panic!("Two bits cannot have value outside [0,3]."),
+            });
+        }
+
+        Ok(string_repr)
+    }
+}
+
+/// Generic counterpart to [get_canonical_repr]/[kmer_to_bits] for any
+/// [KmerEncoding]: encodes `kmer` and its reverse complement and returns
+/// whichever encodes smaller. Unlike [CanonicalKmerIter], this re-encodes
+/// `kmer` from scratch every call rather than sliding an incremental
+/// accumulator, since `KmerEncoding` doesn't expose the bit operations an
+/// incremental update would need; it exists so wide k-mers (k > 32) can
+/// still be classified, just without that optimization.
+pub fn canonical_kmer<E: KmerEncoding>(kmer: &str) -> Result<E> {
+    let revcomp = reverse_complement(kmer)?;
+    let fwd = E::encode(kmer)?;
+    let rev = E::encode(&revcomp)?;
+    Ok(std::cmp::min(fwd, rev))
+}
+
+fn read_kmer_lines<E: KmerEncoding>(contents: &str) -> Result<HashSet<E>> {
+    let mut kmers = HashSet::new();
+
+    for (line_num, line) in contents.lines().enumerate() {
+        let kmer = line.split_whitespace().next()
+            .ok_or(KmerError::BadKmerFile(line_num))?;
+        kmers.insert(E::encode(kmer)?);
+    }
+
+    Ok(kmers)
+}
+
+/// A [KmerSet]-like `HashSet`, backed by whichever [KmerEncoding] is wide
+/// enough to hold the k-mer length actually found in the file.
+pub enum AnyKmerSet {
+    Narrow(HashSet<u64>),
+    Wide(HashSet<u128>),
+    Huge(HashSet<BigKmer>),
+}
+
+/// Like [read_kmers_into_set], but not limited to k <= 32: picks the
+/// narrowest [KmerEncoding] that can hold the k-mer length found on the
+/// file's first line. This matters for trio-binning workflows built around
+/// meryl/merqury, which commonly use k in the 21-51 range.
+pub fn read_kmers_into_any_set(file: File) -> Result<AnyKmerSet> {
+    let mut contents = String::new();
+    BufReader::new(file).read_to_string(&mut contents)?;
+
+    let k = contents.lines().next().map(|l| l.trim().len()).unwrap_or(0);
+
+    if k <= MAX_KMER_LENGTH {
+        Ok(AnyKmerSet::Narrow(read_kmer_lines(&contents)?))
+    } else if k <= MAX_KMER_LENGTH_U128 {
+        Ok(AnyKmerSet::Wide(read_kmer_lines(&contents)?))
+    } else if k <= MAX_KMER_LENGTH_BIG {
+        Ok(AnyKmerSet::Huge(read_kmer_lines(&contents)?))
+    } else {
+        Err(KmerError::LengthError(k, MAX_KMER_LENGTH_BIG))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -157,6 +429,70 @@ mod tests {
         assert_eq!(kmer1, get_canonical_repr(&kmer2).unwrap());
     }
 
+    #[test]
+    fn canonical_kmer_iter_matches_whole_kmer_encoding() {
+        let seq = "ACTGACTGAC";
+        let k = 5;
+
+        let expected: Vec<u64> = (0..=seq.len() - k).map(|i| {
+            let window = &seq[i..i + k];
+            kmer_to_bits(&get_canonical_repr(window).unwrap()).unwrap()
+        }).collect();
+
+        let actual: Vec<u64> = CanonicalKmerIter::new(seq, k).unwrap().collect();
+
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn canonical_kmer_iter_resets_on_ambiguous_base() {
+        let seq = "ACTGNACTGA";
+        let k = 4;
+
+        // no 4-mer spans the N, so every emitted value should come from one
+        // of the two unbroken halves on either side of it
+        let valid_kmers: Vec<u64> = ["ACTG", "CTGA"].iter()
+            .map(|s| kmer_to_bits(&get_canonical_repr(s).unwrap()).unwrap())
+            .collect();
+
+        for canon in CanonicalKmerIter::new(seq, k).unwrap() {
+            assert!(valid_kmers.contains(&canon));
+        }
+    }
+
+    #[test]
+    fn canonical_kmer_matches_get_canonical_repr() {
+        let kmer = "ACTGACTGAC";
+        let expected = kmer_to_bits(&get_canonical_repr(kmer).unwrap()).unwrap();
+        let actual: u64 = canonical_kmer(kmer).unwrap();
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn u128_encoding_reversible() {
+        let kmer = "ACTG".repeat(14); // 56bp, beyond u64's 32-base cap
+        let bits = <u128 as KmerEncoding>::encode(&kmer).unwrap();
+        assert_eq!(bits.decode(kmer.len()).unwrap(), kmer);
+    }
+
+    #[test]
+    fn big_kmer_encoding_reversible() {
+        let kmer = "ACTG".repeat(30); // 120 bases, beyond u128's 64-base cap
+        let bits = BigKmer::encode(&kmer).unwrap();
+        assert_eq!(bits.decode(kmer.len()).unwrap(), kmer);
+    }
+
+    #[test]
+    fn read_kmer_lines_picks_encoding_by_caller_choice() {
+        // read_kmers_into_any_set picks one of the three encodings based on
+        // the k-mer length on the file's first line; this skips that choice
+        // and checks that read_kmer_lines parses in-memory text into the
+        // encoding the caller asks for.
+        let lines = "ACTGACTGACTGACTGACTGACTGACTGACTGACTGACTG\n"; // 40bp, > u64's cap
+        let set: HashSet<u128> = read_kmer_lines(lines).unwrap();
+        assert_eq!(set.len(), 1);
+    }
+
     #[test]
     #[should_panic]
     fn invalid_kmer_base() {
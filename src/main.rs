@@ -31,6 +31,7 @@ fn main() {
     let gz_reader = fastq::Reader::new(gz);
     for result in gz_reader {
         let record = result.unwrap();
-        println!("ID:{}\tSEQ:{}", record.id(), record.seq());
+        println!("ID:{}\tSEQ:{}", record.id_str().unwrap(),
+                  record.seq_str().unwrap());
     }
 }
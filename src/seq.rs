@@ -1,14 +1,21 @@
 extern crate seq_io;
 extern crate flate2;
+extern crate bzip2;
+extern crate xz2;
+extern crate zstd;
 extern crate rust_htslib;
 
-use std::io::Read;
+use std::io::{Read, Write, BufRead, BufReader, BufWriter};
 use std::fs::File;
 use std::{error, result, fmt};
 use self::seq_io::{fasta, fastq};
 use self::seq_io::fasta::Record as FastaRecord;
 use self::seq_io::fastq::Record as FastqRecord;
+use self::flate2::Compression;
 use self::flate2::read::GzDecoder;
+use self::flate2::write::GzEncoder;
+use self::bzip2::read::BzDecoder;
+use self::xz2::read::XzDecoder;
 use self::rust_htslib::bam::{self, Read as BamRead, ReadError};
 
 #[derive(Debug)]
@@ -18,7 +25,7 @@ pub struct ExtensionError {
 
 impl fmt::Display for ExtensionError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}: cannot determine file type from extension", self.path)
+        write!(f, "{}: cannot determine file type", self.path)
     }
 }
 
@@ -30,6 +37,9 @@ pub struct SeqRecord {
     pub id: String,
     pub seq: String,
     pub entry_string: String,
+    /// per-base quality string in the same order as `seq`, if the input
+    /// format carries qualities (fastq and bam do; fasta does not)
+    pub qual: Option<String>,
 }
 
 pub enum SeqReader<T: Read + Send + 'static> {
@@ -38,34 +48,87 @@ pub enum SeqReader<T: Read + Send + 'static> {
     Bam(bam::Reader, bam::record::Record),
 }
 
-fn open_gz_or_uncompressed(filename: &str) ->
-    Result<Box<dyn Read + Send + 'static>> {
-    if filename.ends_with(".gz") {
-        Ok(Box::new(GzDecoder::new(File::open(filename)?)))
+/// compression extensions stripped off an output path before `SeqWriter`
+/// looks at its fasta/fastq extension; input compression is detected from
+/// the file's content, not these extensions
+const COMPRESSION_EXTENSIONS: &[&str] = &[".gz", ".bz2", ".xz", ".zst"];
+
+/// Strips a trailing compression extension off `path`, if it has one, so
+/// that the remaining extension (e.g. ".fasta", ".fq") can pick
+/// `SeqWriter`'s output format. `SeqReader::from_path` does not use it; it
+/// picks the parser from the file's first byte.
+fn strip_compression_extension(path: &str) -> &str {
+    for ext in COMPRESSION_EXTENSIONS {
+        if path.ends_with(ext) {
+            return &path[..path.len() - ext.len()];
+        }
+    }
+    path
+}
+
+/// Opens `filename` and wraps it in the decoder matching whatever
+/// compression its content actually is, regardless of file extension:
+/// gzip (`1F 8B`), bzip2 (`"BZh"`), xz (`FD 37 7A 58 5A 00`), or zstd
+/// (`28 B5 2F FD`). Anything else is assumed to be uncompressed.
+fn open_compressed(filename: &str) -> Result<Box<dyn Read + Send + 'static>> {
+    let file = File::open(filename)?;
+    let mut peek_reader = BufReader::new(file);
+    let magic = peek_reader.fill_buf()?.to_vec();
+
+    if magic.starts_with(&[0x1f, 0x8b]) {
+        Ok(Box::new(GzDecoder::new(peek_reader)))
+    } else if magic.starts_with(b"BZh") {
+        Ok(Box::new(BzDecoder::new(peek_reader)))
+    } else if magic.starts_with(&[0xfd, 0x37, 0x7a, 0x58, 0x5a, 0x00]) {
+        Ok(Box::new(XzDecoder::new(peek_reader)))
+    } else if magic.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+        Ok(Box::new(zstd::Decoder::new(peek_reader)?))
     } else {
-        Ok(Box::new(File::open(filename)?))
+        Ok(Box::new(peek_reader))
     }
 }
 
 impl SeqReader<Box<dyn Read + Send + 'static>> {
+    /// Opens `path` and figures out on its own whether it's FASTA, FASTQ, or
+    /// BAM, gzipped or not, by sniffing its content rather than trusting its
+    /// extension (so e.g. a gzipped fastq file with no `.gz`/`.fq` extension
+    /// still works).
     pub fn from_path(path: &str)
         -> Result<SeqReader<Box<dyn Read + Send + 'static>>> {
-        if path.ends_with(".bam") {
+        if Self::sniff_is_bam(path)? {
             return Ok(SeqReader::Bam(
                     bam::Reader::from_path(path)?,
                     bam::record::Record::new()))
         }
 
-        let read = open_gz_or_uncompressed(path)?;
-        let subpath = if path.ends_with(".gz") { &path[..path.len()-3] }
-                      else { path };
+        let read = open_compressed(path)?;
+        let mut reader = BufReader::new(read);
+        let first_byte = *reader.fill_buf()?.get(0)
+            .ok_or_else(|| Box::new(ExtensionError { path: path.to_string() })
+                as Box<dyn error::Error>)?;
+        let read: Box<dyn Read + Send + 'static> = Box::new(reader);
 
-        if subpath.ends_with(".fasta") || subpath.ends_with(".fa") {
-            Ok(SeqReader::Fasta(fasta::Reader::new(read)))
-        } else if subpath.ends_with(".fastq") || subpath.ends_with(".fq") {
-            Ok(SeqReader::Fastq(fastq::Reader::new(read)))
+        match first_byte {
+            b'>' => Ok(SeqReader::Fasta(fasta::Reader::new(read))),
+            b'@' => Ok(SeqReader::Fastq(fastq::Reader::new(read))),
+            _ => Err(Box::new(ExtensionError { path: path.to_string() })),
+        }
+    }
+
+    /// Peeks `path`'s content for BAM magic (`BAM\x01`), looking inside a
+    /// gzip wrapper first since real BAM files are always BGZF-compressed.
+    fn sniff_is_bam(path: &str) -> Result<bool> {
+        let file = File::open(path)?;
+        let mut peek_reader = BufReader::new(file);
+        let magic = peek_reader.fill_buf()?.to_vec();
+
+        if magic.starts_with(&[0x1f, 0x8b]) {
+            let mut decoder = GzDecoder::new(peek_reader);
+            let mut inner_magic = [0u8; 4];
+            Ok(decoder.read_exact(&mut inner_magic).is_ok()
+                && &inner_magic == b"BAM\x01")
         } else {
-            Err(Box::new(ExtensionError { path: path.to_string() }))
+            Ok(magic.starts_with(b"BAM\x01"))
         }
     }
 }
@@ -92,6 +155,7 @@ impl<T: Read + Send + 'static> Iterator for SeqReader<T> {
                     id: record.id().unwrap().to_string(),
                     seq: String::from_utf8(record.seq().to_vec()).unwrap(),
                     entry_string: String::from_utf8(entry_utf).unwrap(),
+                    qual: None, // fasta has no quality scores
                 }))
             },
 
@@ -112,6 +176,8 @@ impl<T: Read + Send + 'static> Iterator for SeqReader<T> {
                     id: record.id().unwrap().to_string(),
                     seq: String::from_utf8(record.seq().to_vec()).unwrap(),
                     entry_string: String::from_utf8(entry_utf).unwrap(),
+                    qual: Some(String::from_utf8(record.qual().to_vec())
+                               .unwrap()),
                 }))
             },
 
@@ -126,8 +192,16 @@ impl<T: Read + Send + 'static> Iterator for SeqReader<T> {
 
                 let id = String::from_utf8(record.qname().to_vec()).unwrap();
                 let seq = String::from_utf8(record.seq().as_bytes()).unwrap();
+                // a bam record with no stored qualities fills record.qual()
+                // with 0xff bytes rather than real Phred scores; `+ 33` on
+                // those would overflow, so fall back to the same placeholder
+                // used when writing a qual-less record out as fastq
                 let qual = String::from_utf8(
-                    record.qual().iter().map(|q| q + 33).collect()).unwrap();
+                    record.qual().iter().map(|&q| if q == 0xff {
+                        DEFAULT_FASTQ_QUAL_CHAR
+                    } else {
+                        q + 33
+                    }).collect()).unwrap();
 
                 // write bam entries as fastq because there's really no good
                 // reason to output unaligned reads as bam.
@@ -138,8 +212,272 @@ impl<T: Read + Send + 'static> Iterator for SeqReader<T> {
                     id: id,
                     seq: seq,
                     entry_string: entry_string,
+                    qual: Some(qual),
                 }))
             },
         }
     }
 }
+
+/// default quality string used when writing a fastq entry for a record that
+/// has no quality scores of its own (e.g. a fasta record being converted to
+/// fastq); Phred+33 'I' is a quality score of 40.
+const DEFAULT_FASTQ_QUAL_CHAR: u8 = b'I';
+
+/// Mirrors `SeqReader`: picks a fasta or fastq writer, gzip-compressed or
+/// not, from `path`'s extension. Unlike writing out a record's own
+/// `entry_string`, `SeqWriter::write_record` rebuilds the entry from
+/// `id`/`seq`/`qual`, so it can actually convert between formats (e.g. bam
+/// input to fasta output) instead of just replaying whatever format the
+/// record was read in.
+pub enum SeqWriter {
+    Fasta(Box<dyn Write + Send>),
+    Fastq(Box<dyn Write + Send>),
+}
+
+impl SeqWriter {
+    pub fn from_path(path: &str) -> Result<SeqWriter> {
+        let gzip = path.ends_with(".gz");
+        let subpath = strip_compression_extension(path);
+
+        let writer: Box<dyn Write + Send> = if gzip {
+            Box::new(BufWriter::new(GzEncoder::new(
+                File::create(path)?, Compression::default())))
+        } else {
+            Box::new(BufWriter::new(File::create(path)?))
+        };
+
+        if subpath.ends_with(".fasta") || subpath.ends_with(".fa") {
+            Ok(SeqWriter::Fasta(writer))
+        } else if subpath.ends_with(".fastq") || subpath.ends_with(".fq") {
+            Ok(SeqWriter::Fastq(writer))
+        } else {
+            Err(Box::new(ExtensionError { path: path.to_string() }))
+        }
+    }
+
+    /// Writes `record` in this writer's format. If this is a fastq writer
+    /// and `record` has no quality scores of its own, every base is given
+    /// the same placeholder quality instead of failing.
+    pub fn write_record(&mut self, record: &SeqRecord) -> Result<()> {
+        match self {
+            SeqWriter::Fasta(w) => {
+                write!(w, ">{}\n{}\n", record.id, record.seq)?;
+            }
+            SeqWriter::Fastq(w) => {
+                match &record.qual {
+                    Some(qual) => write!(w, "@{}\n{}\n+\n{}\n",
+                                         record.id, record.seq, qual)?,
+                    None => {
+                        let qual = vec![DEFAULT_FASTQ_QUAL_CHAR; record.seq.len()];
+                        write!(w, "@{}\n{}\n+\n{}\n", record.id, record.seq,
+                               String::from_utf8(qual).unwrap())?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct PairError {
+    message: String,
+}
+
+impl PairError {
+    fn new(message: String) -> Box<PairError> {
+        Box::new(PairError { message: message })
+    }
+}
+
+impl fmt::Display for PairError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl error::Error for PairError {}
+
+/// Strips a trailing `/1`/`/2`, or a trailing " 1:..."/" 2:..." mate-number
+/// suffix, off a read ID, so mate IDs that only differ by which mate they
+/// are can be compared for equality.
+fn strip_mate_suffix(id: &str) -> &str {
+    if id.ends_with("/1") || id.ends_with("/2") {
+        return &id[..id.len() - 2];
+    }
+
+    match id.find(" 1:").or_else(|| id.find(" 2:")) {
+        Some(pos) => &id[..pos],
+        None => id,
+    }
+}
+
+/// Reads paired-end data as matched `(mate 1, mate 2)` records, either from
+/// two separate `SeqReader`s (one per mate file) or by de-interleaving a
+/// single stream that alternates mate 1, mate 2, mate 1, mate 2, ... Checks
+/// that each pair's IDs agree (after stripping a `/1`,`/2`,` 1:`,` 2:` mate
+/// suffix) and that the two sides don't run out of records at different
+/// times, so a mismatched trio binning input fails loudly instead of
+/// silently pairing the wrong reads.
+pub enum PairedSeqReader {
+    Separate(SeqReader<Box<dyn Read + Send + 'static>>,
+             SeqReader<Box<dyn Read + Send + 'static>>),
+    Interleaved(SeqReader<Box<dyn Read + Send + 'static>>),
+}
+
+impl PairedSeqReader {
+    /// Reads mate 1 from `path_1` and mate 2 from `path_2`.
+    pub fn from_paths(path_1: &str, path_2: &str) -> Result<PairedSeqReader> {
+        Ok(PairedSeqReader::Separate(
+                SeqReader::from_path(path_1)?,
+                SeqReader::from_path(path_2)?))
+    }
+
+    /// Reads both mates from a single interleaved file: mate 1, mate 2,
+    /// mate 1, mate 2, and so on.
+    pub fn from_interleaved_path(path: &str) -> Result<PairedSeqReader> {
+        Ok(PairedSeqReader::Interleaved(SeqReader::from_path(path)?))
+    }
+}
+
+impl Iterator for PairedSeqReader {
+    type Item = Result<(SeqRecord, SeqRecord)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (record_1, record_2) = match self {
+            PairedSeqReader::Separate(reader_1, reader_2) => {
+                match (reader_1.next(), reader_2.next()) {
+                    (Some(r1), Some(r2)) => (r1, r2),
+                    (None, None) => return None,
+                    (Some(_), None) => return Some(Err(PairError::new(
+                        "mate 1 input has more records than mate 2 input"
+                            .to_string()))),
+                    (None, Some(_)) => return Some(Err(PairError::new(
+                        "mate 2 input has more records than mate 1 input"
+                            .to_string()))),
+                }
+            }
+            PairedSeqReader::Interleaved(reader) => {
+                match (reader.next(), reader.next()) {
+                    (Some(r1), Some(r2)) => (r1, r2),
+                    (None, None) => return None,
+                    (Some(_), None) => return Some(Err(PairError::new(
+                        "interleaved input has an odd number of records, \
+                        so the last record has no mate".to_string()))),
+                    (None, Some(_)) => unreachable!(),
+                }
+            }
+        };
+
+        let record_1 = match record_1 {
+            Ok(r) => r,
+            Err(e) => return Some(Err(e)),
+        };
+        let record_2 = match record_2 {
+            Ok(r) => r,
+            Err(e) => return Some(Err(e)),
+        };
+
+        if strip_mate_suffix(&record_1.id) != strip_mate_suffix(&record_2.id) {
+            return Some(Err(PairError::new(format!(
+                "mate IDs out of sync: \"{}\" (mate 1) vs \"{}\" (mate 2)",
+                record_1.id, record_2.id))));
+        }
+
+        Some(Ok((record_1, record_2)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn strip_mate_suffix_strips_slash_suffix() {
+        assert_eq!(strip_mate_suffix("read1/1"), "read1");
+        assert_eq!(strip_mate_suffix("read1/2"), "read1");
+    }
+
+    #[test]
+    fn strip_mate_suffix_strips_illumina_style_suffix() {
+        assert_eq!(strip_mate_suffix("read1 1:N:0:ATCG"), "read1");
+        assert_eq!(strip_mate_suffix("read1 2:N:0:ATCG"), "read1");
+    }
+
+    #[test]
+    fn strip_mate_suffix_leaves_unsuffixed_ids_alone() {
+        assert_eq!(strip_mate_suffix("read1"), "read1");
+    }
+
+    // a Write + Send sink backed by a Vec<u8> that's still reachable after
+    // being boxed up and handed to a SeqWriter, so write_record's output can
+    // be checked
+    #[derive(Clone)]
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            self.0.lock().unwrap().flush()
+        }
+    }
+
+    #[test]
+    fn write_record_writes_fasta_format() {
+        let buf = Arc::new(Mutex::new(Vec::new()));
+        let mut writer = SeqWriter::Fasta(Box::new(SharedBuf(Arc::clone(&buf))));
+        let record = SeqRecord {
+            id: "test".to_string(),
+            seq: "ACGT".to_string(),
+            entry_string: "@ignored\nACGT\n+\nIIII".to_string(),
+            qual: Some("IIII".to_string()),
+        };
+
+        writer.write_record(&record).unwrap();
+        assert_eq!(&buf.lock().unwrap()[..], b">test\nACGT\n");
+    }
+
+    #[test]
+    fn write_record_fills_in_placeholder_quality_for_qual_less_fastq_output() {
+        let buf = Arc::new(Mutex::new(Vec::new()));
+        let mut writer = SeqWriter::Fastq(Box::new(SharedBuf(Arc::clone(&buf))));
+        let record = SeqRecord {
+            id: "test".to_string(),
+            seq: "ACGT".to_string(),
+            entry_string: ">ignored\nACGT".to_string(),
+            qual: None, // as if converted from a fasta record
+        };
+
+        writer.write_record(&record).unwrap();
+        assert_eq!(&buf.lock().unwrap()[..], b"@test\nACGT\n+\nIIII\n");
+    }
+
+    #[test]
+    fn open_compressed_sniffs_gzip_regardless_of_filename() {
+        // give the file a misleading extension to make sure open_compressed
+        // is looking at the magic bytes, not the name
+        let mut path = std::env::temp_dir();
+        path.push(format!("trio_binning_test_{}.txt",
+                          std::process::id()));
+
+        {
+            let file = File::create(&path).unwrap();
+            let mut encoder = GzEncoder::new(file, Compression::default());
+            encoder.write_all(b">id\nACGT\n").unwrap();
+        }
+
+        let mut reader = open_compressed(path.to_str().unwrap()).unwrap();
+        let mut decompressed = String::new();
+        reader.read_to_string(&mut decompressed).unwrap();
+
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(decompressed, ">id\nACGT\n");
+    }
+}
@@ -1,19 +1,7 @@
-use std::io::{self, BufRead, BufReader, Lines, Read};
+use std::io::{self, Read};
 use std::error;
 use std::fmt;
 
-/// a variant of try!/? to use in functions/methods that return
-/// Option<Result<T,E>>. For example, if you call a function that returns a
-/// Result from inside an iterator's next() method and want to propogate any
-/// errors that come from that function, you can't use try! because next() has
-/// to return an Option but try! returns a Result.
-macro_rules! try_or_some_err {
-    ($x:expr) => (match $x {
-        Ok(val) => val,
-        Err(err) => return Some(Err(err)),
-    });
-}
-
 #[derive(Debug)]
 pub enum FastaError {
     Parse,
@@ -82,9 +70,26 @@ fn get_id_from_defline(defline: &str) -> Result<&str, FastaError> {
         .map(|w| w.trim_left_matches('>')) // trim the '>' delimiter
 }
 
+/// number of bytes read from the underlying reader at a time; entries larger
+/// than this just cause the buffer to grow, they aren't truncated
+const BLOCK_SIZE: usize = 256 * 1024;
+
+/// A fasta reader that reads the underlying file in large blocks instead of
+/// one line at a time, and hands out entries by slicing the buffered bytes
+/// instead of cloning the previous entry on every call to `next()`.
 pub struct Reader<T> {
-    lines_iter: Lines<BufReader<T>>,
-    current_entry: Record,
+    source: T,
+    buf: Vec<u8>,
+    /// start of the entry that hasn't been handed out yet
+    pos: usize,
+    /// end of the valid (already-read) data in `buf`
+    filled: usize,
+    /// true once the underlying reader has returned 0 bytes
+    eof: bool,
+    /// how far into `buf` we've already looked for the start of the next
+    /// entry without finding one, so we don't rescan the same bytes every
+    /// time `fill_more` pulls in another block
+    scan_from: usize,
     current_line_number: usize,
 }
 
@@ -93,76 +98,106 @@ impl<T: Read> Reader<T> {
     /// anything that implements `std::io::Read`, e.g., `std::io::File`
     pub fn new(file: T) -> Reader<T> {
         Reader {
-            lines_iter: BufReader::new(file).lines(),
-            current_entry: Record {
-                id: String::new(),
-                seq: String::new(),
-                entry_string: String::new(),
-            },
+            source: file,
+            buf: vec![0; BLOCK_SIZE],
+            pos: 0,
+            filled: 0,
+            eof: false,
+            scan_from: 1,
             current_line_number: 0,
         }
     }
+
+    /// Compacts any already-consumed bytes out of the front of the buffer,
+    /// growing it if a single entry doesn't fit, then reads another block
+    /// from the underlying reader.
+    fn fill_more(&mut self) -> io::Result<()> {
+        if self.pos > 0 {
+            self.buf.copy_within(self.pos..self.filled, 0);
+            self.filled -= self.pos;
+            self.scan_from = self.scan_from.saturating_sub(self.pos);
+            self.pos = 0;
+        }
+
+        if self.filled == self.buf.len() {
+            let new_len = self.buf.len() * 2;
+            self.buf.resize(new_len, 0);
+        }
+
+        let n = self.source.read(&mut self.buf[self.filled..])?;
+        if n == 0 {
+            self.eof = true;
+        } else {
+            self.filled += n;
+        }
+
+        Ok(())
+    }
+
+    /// Looks for the '>' that starts the entry following the one at
+    /// `self.pos`, i.e., a '>' immediately following a '\n'. Returns its
+    /// index into `buf` if one has been read in already.
+    fn find_next_entry_start(&mut self) -> Option<usize> {
+        let mut i = if self.scan_from > self.pos { self.scan_from }
+                    else { self.pos + 1 };
+
+        while i < self.filled {
+            if self.buf[i] == b'>' && self.buf[i - 1] == b'\n' {
+                return Some(i);
+            }
+            i += 1;
+        }
+
+        self.scan_from = i;
+        None
+    }
+
+    /// Turns the buffered bytes from `self.pos` to `boundary` into a Record,
+    /// then advances `self.pos` past them.
+    fn take_entry(&mut self, boundary: usize) -> Result<Record, FastaError> {
+        let entry_string = String::from_utf8(self.buf[self.pos..boundary].to_vec())
+            .map_err(|_| FastaError::Parse)?;
+        self.current_line_number += entry_string.matches('\n').count() + 1;
+
+        self.pos = boundary;
+        self.scan_from = self.pos + 1;
+
+        Record::new(&entry_string)
+    }
 }
 
 impl<T: Read> Iterator for Reader<T> {
     type Item = Result<Record, FastaError>;
 
     fn next(&mut self) -> Option<Result<Record, FastaError>> {
-        while let Some(result) = self.lines_iter.next() {
-            self.current_line_number += 1;
-
-            let line = try_or_some_err!(result.map_err(|e| FastaError::Io(e)));
-
-            if line.starts_with(">") {
-                if self.current_entry.entry_string != "" {
-                    // we have reached the beginning of a new entry, so we move
-                    // the instance of Record representing the current one to a
-                    // new variable, start a new instance of Record for the new
-                    // one, and then return the completed one.
-                    let finished_entry = self.current_entry.clone();
-                    self.current_entry = Record {
-                        id: try_or_some_err!(get_id_from_defline(&line)
-                            .map_err(|_| FastaError::ParseLine(
-                                self.current_line_number)))
-                            .to_string(),
-                        seq: String::new(),
-                        entry_string: String::from(line),
-                    };
-                    return Some(Ok(finished_entry));
-                } else {
-                    // we're on the first line, so don't return anything; just
-                    // update the entry string and id.
-                    self.current_entry.entry_string.push_str(&line);
-                    self.current_entry.id = try_or_some_err!(
-                        get_id_from_defline(&line)
-                        .map_err(|_| FastaError::ParseLine(
-                                self.current_line_number)))
-                        .to_string();
+        loop {
+            if self.pos == self.filled {
+                if self.eof {
+                    return None;
                 }
-            } else { // line is not the defline
-                if self.current_entry.id == "" {
-                    // must start the file with a defline!
-                    return Some(Err(FastaError::ParseLine(
-                                self.current_line_number)));
-                } else {
-                    self.current_entry.entry_string.push_str(&line);
-                    self.current_entry.seq.push_str(&line.trim());
+                if let Err(e) = self.fill_more() {
+                    return Some(Err(FastaError::Io(e)));
                 }
+                continue;
             }
-        }
-       
-        // we've reached EOF, so return the final entry, or None if we already
-        // did that
-        if self.current_entry.entry_string != "" {
-            let finished_entry = self.current_entry.clone();
 
-            // change current_entry.entry_string to an empty String so that the
-            // next time next() is called, we know to return None
-            self.current_entry.entry_string = String::new();
+            if self.buf[self.pos] != b'>' {
+                // must start the entry with a defline!
+                return Some(Err(FastaError::ParseLine(self.current_line_number)));
+            }
 
-            Some(Ok(finished_entry))
-        } else {
-            None
+            if let Some(boundary) = self.find_next_entry_start() {
+                return Some(self.take_entry(boundary));
+            }
+
+            if self.eof {
+                let boundary = self.filled;
+                return Some(self.take_entry(boundary));
+            }
+
+            if let Err(e) = self.fill_more() {
+                return Some(Err(FastaError::Io(e)));
+            }
         }
     }
 }
@@ -180,4 +215,34 @@ mod tests {
         assert_eq!(rec.seq(), "ACTGAAAAACGT".to_string());
         assert_eq!(rec.to_string(), entry_string);
     }
+
+    #[test]
+    fn fasta_reader_multiple_entries() {
+        let data = ">id1 desc\nACTG\nAAAA\n>id2\nGGGG\n";
+        let mut reader = Reader::new(data.as_bytes());
+
+        let rec1 = reader.next().unwrap().unwrap();
+        assert_eq!(rec1.id(), "id1");
+        assert_eq!(rec1.seq(), "ACTGAAAA");
+
+        let rec2 = reader.next().unwrap().unwrap();
+        assert_eq!(rec2.id(), "id2");
+        assert_eq!(rec2.seq(), "GGGG");
+
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn fasta_reader_entry_spanning_block_boundary() {
+        // a single sequence line longer than BLOCK_SIZE forces fill_more to
+        // grow the buffer instead of truncating the entry
+        let seq: String = std::iter::repeat('A').take(BLOCK_SIZE * 2).collect();
+        let data = format!(">id\n{}\n", seq);
+        let mut reader = Reader::new(data.as_bytes());
+
+        let rec = reader.next().unwrap().unwrap();
+        assert_eq!(rec.id(), "id");
+        assert_eq!(rec.seq(), seq);
+        assert!(reader.next().is_none());
+    }
 }
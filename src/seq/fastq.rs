@@ -1,7 +1,7 @@
-use std::io::{self, BufRead, BufReader, Lines, Read};
+use std::io::{self, BufRead, BufReader, Read};
 use std::error;
 use std::fmt;
-use std::iter::Enumerate;
+use std::str;
 
 #[derive(Debug)]
 pub enum FastqError {
@@ -10,6 +10,7 @@ pub enum FastqError {
     DefLine,
     ParseLine(usize),
     Io(io::Error),
+    Utf8(str::Utf8Error),
 }
 
 impl fmt::Display for FastqError {
@@ -21,101 +22,146 @@ impl fmt::Display for FastqError {
             FastqError::ParseLine(line) =>
                 write!(f, "Problem with entry starting on line {}", line),
             FastqError::Io(e) => write!(f, "{}", e),
+            FastqError::Utf8(e) => write!(f, "{}", e),
         }
     }
 }
 
 impl error::Error for FastqError {}
 
+/// A single fastq entry, stored as one byte buffer plus the (start, end)
+/// ranges of its id, seq, and qual lines within that buffer, rather than as
+/// three separately-allocated `String`s.
 #[derive(Clone, Debug)]
 pub struct Record {
-    id: String,
-    seq: String,
-    qual: String,
-    entry_string: String,
+    buf: Vec<u8>,
+    id: (usize, usize),
+    seq: (usize, usize),
+    qual: (usize, usize),
 }
 
 impl Record {
-    /// Creates a new Record from a &String containing a fastq entry.
-    /// Returns None if the string is empty.
-    pub fn new(entry_string: &String) -> Result<Record, FastqError> {
-        let lines: Vec<&str> = entry_string.split('\n').collect();
-
-        Ok(Record {
-            id: lines.get(0).ok_or(FastqError::MissingLine)
-                .and_then(|l| get_id_from_defline(&l))?.to_string(),
-            seq: lines.get(1).ok_or(FastqError::MissingLine)
-                .map(|l| l.trim().to_string())?,
-            qual: lines.get(3).ok_or(FastqError::MissingLine)
-                .map(|l| l.trim().to_string())?,
-            entry_string: entry_string.to_owned(),
-        })
+    pub fn id(&self) -> &[u8] { &self.buf[self.id.0..self.id.1] }
+    pub fn seq(&self) -> &[u8] { &self.buf[self.seq.0..self.seq.1] }
+    pub fn qual(&self) -> &[u8] { &self.buf[self.qual.0..self.qual.1] }
+
+    /// Checked `&str` view of `id`, only paying for utf8 validation if the
+    /// caller actually asks for one.
+    pub fn id_str(&self) -> Result<&str, FastqError> {
+        str::from_utf8(self.id()).map_err(FastqError::Utf8)
     }
 
-    pub fn id(&self) -> &str { &self.id }
-    pub fn seq(&self) -> &str { &self.seq }
-    pub fn qual(&self) -> &str { &self.qual }
-    pub fn to_string(&self) -> &str { &self.entry_string }
-}
+    pub fn seq_str(&self) -> Result<&str, FastqError> {
+        str::from_utf8(self.seq()).map_err(FastqError::Utf8)
+    }
 
-/// Takes a fasta defline (e.g., "@seqID sequence desccription") and returns the
-/// ID of the entry (e.g., "SeqID")
-///
-/// # Errors
-/// Returns Err("Parsing error!") if an ID cannot be found in the defline, e.g.,
-/// if the defline is empty or there is a space after the ">"
-fn get_id_from_defline(defline: &str) -> Result<&str, FastqError> {
-    defline.split_whitespace().next() // get the first word
-        .ok_or(FastqError::DefLine)
-        .map(|w| w.trim_left_matches('@')) // trim the '@' delimiter
+    pub fn qual_str(&self) -> Result<&str, FastqError> {
+        str::from_utf8(self.qual()).map_err(FastqError::Utf8)
+    }
 }
 
+/// number of bytes a fresh `Reader`'s scratch buffer starts out with; it
+/// grows as needed and is cleared, not reallocated, between entries
+const INITIAL_BUF_CAPACITY: usize = 4 * 1024;
+
 pub struct Reader<T> {
-    lines_enum_iter: Enumerate<Lines<BufReader<T>>>,
+    source: BufReader<T>,
+    /// scratch buffer for the four lines of the entry currently being
+    /// read; cleared and reused by `next()` to avoid reading line-by-line
+    /// into a fresh allocation each time. Parsing itself never copies out
+    /// of it, but each emitted `Record` still takes its own `buf.clone()`,
+    /// since a `Record` has to be able to outlive the next call to
+    /// `next()` and `Iterator::next()` gives us no way to tie its lifetime
+    /// to this buffer instead.
+    buf: Vec<u8>,
     line_num: usize,
 }
 
 impl<T: Read> Reader<T> {
-    /// Creates a new fasta Reader that reads from `file`. `file` can be
+    /// Creates a new fastq Reader that reads from `file`. `file` can be
     /// anything that implements `std::io::Read`, e.g., `std::io::File`
     pub fn new(file: T) -> Reader<T> {
         Reader {
-            lines_enum_iter: BufReader::new(file).lines().enumerate(),
+            source: BufReader::new(file),
+            buf: Vec::with_capacity(INITIAL_BUF_CAPACITY),
             line_num: 0,
         }
     }
+
+    /// Reads one line onto the end of `self.buf` via `read_until`, and
+    /// returns the (start, end) byte range of its content with the
+    /// trailing newline (and a preceding `\r`, if any) stripped off.
+    /// Returns `None` if there was nothing left to read.
+    fn read_line(&mut self) -> io::Result<Option<(usize, usize)>> {
+        let start = self.buf.len();
+        let n = self.source.read_until(b'\n', &mut self.buf)?;
+        if n == 0 {
+            return Ok(None);
+        }
+
+        let mut end = self.buf.len();
+        if self.buf[end - 1] == b'\n' {
+            end -= 1;
+            if end > start && self.buf[end - 1] == b'\r' {
+                end -= 1;
+            }
+        }
+
+        Ok(Some((start, end)))
+    }
 }
 
 impl<T: Read> Iterator for Reader<T> {
     type Item = Result<Record, FastqError>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        // if we're at EOF before starting a new entry, return None
-        let (line_num, mut entry_string) = match self.lines_enum_iter.next() {
-            Some((i,r)) => match r {
-                Ok(l) => (i,l+"\n"),
-                Err(e) => return Some(Err(FastqError::Io(e))),
-            },
-            None => return None,
-        };
-
-        self.line_num = line_num + 1; // lines are 1-indexed
+        self.buf.clear();
 
-        // if we reach EOF before finishing the entry, that's an incomplete
-        // entry and indicative of a misformatted fastq file
-        for _ in 0..3 {
-            let next_line = match self.lines_enum_iter.next() {
-                Some((_i,r)) => match r {
-                    Ok(l) => l,
-                    Err(e) => return Some(Err(FastqError::Io(e))),
-                },
-                None => return Some(Err(FastqError::ParseLine(self.line_num))),
-            };
+        let defline = match self.read_line() {
+            Ok(Some(r)) => r,
+            Ok(None) => return None, // cleanly done, no entry in progress
+            Err(e) => return Some(Err(FastqError::Io(e))),
+        };
+        let entry_line_num = self.line_num + 1;
+        self.line_num += 1;
+
+        // the defline is the only place we ever look for a leading '@', so
+        // a '@' appearing in, say, the quality line (a perfectly valid
+        // Phred+33 character) is never mistaken for the start of an entry
+        if self.buf.get(defline.0) != Some(&b'@') {
+            return Some(Err(FastqError::ParseLine(entry_line_num)));
+        }
+        let id_start = defline.0 + 1;
+        let id_end = self.buf[id_start..defline.1].iter()
+            .position(|b| b.is_ascii_whitespace())
+            .map_or(defline.1, |i| id_start + i);
+        let id = (id_start, id_end);
+
+        let seq = match self.read_line() {
+            Ok(Some(r)) => r,
+            Ok(None) => return Some(Err(FastqError::ParseLine(entry_line_num))),
+            Err(e) => return Some(Err(FastqError::Io(e))),
+        };
+        self.line_num += 1;
 
-            entry_string.push_str(&(next_line + "\n"));
+        let plus_line = match self.read_line() {
+            Ok(Some(r)) => r,
+            Ok(None) => return Some(Err(FastqError::ParseLine(entry_line_num))),
+            Err(e) => return Some(Err(FastqError::Io(e))),
+        };
+        self.line_num += 1;
+        if self.buf.get(plus_line.0) != Some(&b'+') {
+            return Some(Err(FastqError::ParseLine(entry_line_num)));
         }
 
-        Some(Record::new(&entry_string))
+        let qual = match self.read_line() {
+            Ok(Some(r)) => r,
+            Ok(None) => return Some(Err(FastqError::ParseLine(entry_line_num))),
+            Err(e) => return Some(Err(FastqError::Io(e))),
+        };
+        self.line_num += 1;
+
+        Some(Ok(Record { buf: self.buf.clone(), id, seq, qual }))
     }
 }
 
@@ -125,12 +171,59 @@ mod tests {
     use super::*;
 
     #[test]
-    fn fastq_record() {
-        let entry_string = "@id\nACTG\n+\nQQQQ".to_string();
-        let rec = Record::new(&entry_string).unwrap();
-        assert_eq!(rec.id(), "id".to_string());
-        assert_eq!(rec.seq(), "ACTG".to_string());
-        assert_eq!(rec.qual(), "QQQQ".to_string());
-        assert_eq!(rec.to_string(), entry_string);
+    fn fastq_reader_single_entry() {
+        let data = b"@id desc\nACTG\n+\nQQQQ\n".to_vec();
+        let mut reader = Reader::new(&data[..]);
+
+        let rec = reader.next().unwrap().unwrap();
+        assert_eq!(rec.id(), b"id");
+        assert_eq!(rec.seq(), b"ACTG");
+        assert_eq!(rec.qual(), b"QQQQ");
+        assert_eq!(rec.id_str().unwrap(), "id");
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn fastq_reader_multiple_entries() {
+        let data = b"@id1\nACTG\n+\nQQQQ\n@id2\nGGGG\n+\nIIII\n".to_vec();
+        let mut reader = Reader::new(&data[..]);
+
+        let rec1 = reader.next().unwrap().unwrap();
+        assert_eq!(rec1.id(), b"id1");
+        assert_eq!(rec1.seq(), b"ACTG");
+
+        let rec2 = reader.next().unwrap().unwrap();
+        assert_eq!(rec2.id(), b"id2");
+        assert_eq!(rec2.seq(), b"GGGG");
+
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn fastq_reader_qual_line_starting_with_at_sign_is_not_a_boundary() {
+        // '@' is a valid (if low) Phred+33 quality character, so it must
+        // not be mistaken for the start of the next entry
+        let data = b"@id1\nACTG\n+\n@QQQ\n@id2\nGGGG\n+\nIIII\n".to_vec();
+        let mut reader = Reader::new(&data[..]);
+
+        let rec1 = reader.next().unwrap().unwrap();
+        assert_eq!(rec1.id(), b"id1");
+        assert_eq!(rec1.qual(), b"@QQQ");
+
+        let rec2 = reader.next().unwrap().unwrap();
+        assert_eq!(rec2.id(), b"id2");
+
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn fastq_reader_truncated_entry_is_a_parse_error() {
+        let data = b"@id1\nACTG\n+\n".to_vec();
+        let mut reader = Reader::new(&data[..]);
+
+        match reader.next() {
+            Some(Err(FastqError::ParseLine(1))) => (),
+            other => panic!("expected ParseLine(1), got {:?}", other),
+        }
     }
 }
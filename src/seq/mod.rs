@@ -1,8 +1,14 @@
+extern crate flate2;
+extern crate rust_htslib;
+
 use std::fmt;
-use std::path::Path;
 use std::fs::File;
-use std::io::{self, Read};
+use std::io::{self, Read, BufRead, BufReader};
+use std::sync::mpsc::{sync_channel, Receiver};
+use std::thread;
 use std::{result,error};
+use self::flate2::read::GzDecoder;
+use self::rust_htslib::bam::{self, Read as BamRead, ReadError as BamReadError};
 
 pub mod fasta;
 pub mod fastq;
@@ -37,20 +43,24 @@ type Result<T> = result::Result<T, ReaderError>;
 pub enum Record {
     Fasta(fasta::Record),
     Fastq(fastq::Record),
+    Bam { id: String, seq: String },
 }
 
 impl Record {
     pub fn id(&self) -> &str {
         match self {
             Record::Fasta(r) => r.id(),
-            Record::Fastq(r) => r.id(),
+            // not handling utf8 errors here because bleh. Maybe later.
+            Record::Fastq(r) => r.id_str().unwrap(),
+            Record::Bam { id, .. } => id,
         }
     }
 
     pub fn seq(&self) -> &str {
         match self {
             Record::Fasta(r) => r.seq(),
-            Record::Fastq(r) => r.seq(),
+            Record::Fastq(r) => r.seq_str().unwrap(),
+            Record::Bam { seq, .. } => seq,
         }
     }
 }
@@ -59,38 +69,182 @@ impl fmt::Display for Record {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Record::Fasta(r) => write!(f, "{}", r.to_string()),
-            Record::Fastq(r) => write!(f, "{}", r.to_string()),
+            Record::Fastq(r) => write!(f, "@{}\n{}\n+\n{}",
+                r.id_str().unwrap(), r.seq_str().unwrap(),
+                r.qual_str().unwrap()),
+            Record::Bam { id, seq } => write!(f, ">{}\n{}", id, seq),
         }
     }
 }
 
-/// Gets the extension from a filename.
-///
-/// # Errors
-/// `ReaderError::Other` if filename has no extension
-fn get_extension(filename: &str) -> Result<&str> {
-    Path::new(filename).extension().and_then(|e| e.to_str())
-        .ok_or(ReaderError::Other("Filename has no extension!".to_owned()))
-}
-
 /// wrapper for various file types containing sets of sequences, so that the
 /// user can just call next() and get generic Records back without worrying
 /// about what kind of file is being parsed
 pub enum Reader<T> {
     Fasta(fasta::Reader<T>),
     Fastq(fastq::Reader<T>),
+    Bam(bam::Reader, bam::record::Record),
+}
+
+/// Returns whether the first couple of bytes of `magic` are the gzip magic
+/// number. Returns `false` if `magic` is too short to tell.
+fn is_gzip(magic: &[u8]) -> bool {
+    magic.len() >= 2 && magic[0] == 0x1f && magic[1] == 0x8b
+}
+
+/// Wraps a boxed reader whose first unread byte is known, dispatching to the
+/// fasta or fastq parser based on that byte the same way `>`/`@` mark
+/// deflines in those formats.
+fn open_by_first_byte(first_byte: u8, reader: Box<dyn Read + Send>)
+    -> Result<Reader<Box<dyn Read + Send>>> {
+    match first_byte {
+        b'>' => Ok(Reader::Fasta(fasta::Reader::new(reader))),
+        b'@' => Ok(Reader::Fastq(fastq::Reader::new(reader))),
+        _ => Err(ReaderError::Other(
+                "Cannot determine file type from content.".to_owned())),
+    }
+}
+
+impl Reader<Box<dyn Read + Send>> {
+    /// Opens `filename` and figures out on its own whether it's FASTA,
+    /// FASTQ, or BAM, gzipped or not, by sniffing the first few bytes of the
+    /// stream rather than trusting the filename's extension.
+    pub fn from_filename(filename: &str) -> Result<Reader<Box<dyn Read + Send>>> {
+        let file = File::open(filename).map_err(ReaderError::Io)?;
+        let mut peek_reader = BufReader::new(file);
+        let magic = peek_reader.fill_buf().map_err(ReaderError::Io)?.to_vec();
+
+        if is_gzip(&magic) {
+            // could be an ordinary gzipped fasta/fastq, or it could be a
+            // BGZF-compressed BAM file, which is also valid gzip; peek
+            // inside the decompressed stream to tell the two apart
+            let mut decoder = GzDecoder::new(peek_reader);
+            let mut inner_magic = [0u8; 4];
+            let is_bam = decoder.read_exact(&mut inner_magic).is_ok()
+                && &inner_magic == b"BAM\x01";
+
+            if is_bam {
+                return Ok(Reader::Bam(
+                        bam::Reader::from_path(filename)
+                            .map_err(|e| ReaderError::Other(e.to_string()))?,
+                        bam::record::Record::new()));
+            }
+
+            // not a BAM file, so reopen the file and hand a fresh decoder to
+            // the fasta/fastq parser instead of the partially-read one above
+            let file = File::open(filename).map_err(ReaderError::Io)?;
+            let mut reader = BufReader::new(
+                    Box::new(GzDecoder::new(file)) as Box<dyn Read + Send>);
+            let first_byte = *reader.fill_buf().map_err(ReaderError::Io)?
+                .get(0).ok_or(ReaderError::Other(
+                        "Empty input file.".to_owned()))?;
+            open_by_first_byte(first_byte, Box::new(reader))
+        } else if magic.starts_with(b"BAM\x01") {
+            Ok(Reader::Bam(
+                    bam::Reader::from_path(filename)
+                        .map_err(|e| ReaderError::Other(e.to_string()))?,
+                    bam::record::Record::new()))
+        } else {
+            let first_byte = *magic.get(0).ok_or(ReaderError::Other(
+                    "Empty input file.".to_owned()))?;
+            open_by_first_byte(first_byte, Box::new(peek_reader))
+        }
+    }
+
+    /// Like `from_filename`, but does the actual reading and decompression
+    /// on a dedicated background thread instead of the caller's thread, so
+    /// the caller can be busy classifying one batch of records while the
+    /// next batch is still being read/decompressed. Existing callers of
+    /// `from_filename` are unaffected; this is purely opt-in.
+    ///
+    /// BAM input is rejected rather than spawned: `bam::Reader` isn't `Send`
+    /// in the rust-htslib version this crate is pinned to, so a
+    /// `Reader::Bam` could never be moved onto the background thread. Only
+    /// the fasta/fastq variants, which don't hold a `bam::Reader`, are
+    /// threaded.
+    pub fn from_filename_threaded(filename: &str) -> Result<ThreadedReader> {
+        let reader = match Reader::from_filename(filename)? {
+            Reader::Fasta(r) => NonBamReader::Fasta(r),
+            Reader::Fastq(r) => NonBamReader::Fastq(r),
+            Reader::Bam(..) => return Err(ReaderError::Other(
+                "threaded reading does not support bam input".to_owned())),
+        };
+        let (sender, receiver) = sync_channel(THREADED_CHANNEL_BOUND);
+
+        thread::spawn(move || {
+            let mut reader = reader;
+            let mut chunk = Vec::with_capacity(THREADED_CHUNK_SIZE);
+            while let Some(record) = reader.next() {
+                chunk.push(record);
+                if chunk.len() == THREADED_CHUNK_SIZE {
+                    if sender.send(chunk).is_err() {
+                        return; // the consumer has hung up
+                    }
+                    chunk = Vec::with_capacity(THREADED_CHUNK_SIZE);
+                }
+            }
+            if !chunk.is_empty() {
+                let _ = sender.send(chunk);
+            }
+        });
+
+        Ok(ThreadedReader {
+            receiver,
+            buffer: Vec::new().into_iter(),
+        })
+    }
+}
+
+/// Subset of `Reader` that excludes the BAM variant, so its type doesn't
+/// carry a `bam::Reader` at all; used by `from_filename_threaded` so the
+/// background thread's `Send` bound doesn't depend on `bam::Reader` being
+/// `Send`.
+enum NonBamReader<T> {
+    Fasta(fasta::Reader<T>),
+    Fastq(fastq::Reader<T>),
 }
 
-impl Reader<File> {
-    pub fn from_filename(filename: &str) -> Result<Reader<File>> {
-        let extension = get_extension(filename)?;
-        let file = File::open(filename).map_err(|e| ReaderError::Io(e))?;
+impl<T: Read> Iterator for NonBamReader<T> {
+    type Item = Result<Record>;
 
-        match extension {
-            "fasta" | "fa" => Ok(Reader::Fasta(fasta::Reader::new(file))),
-            "fastq" | "fq" => Ok(Reader::Fastq(fastq::Reader::new(file))),
-            _ => Err(ReaderError::Other(
-                    "Do not recognize filename.".to_owned())),
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            NonBamReader::Fasta(r) => r.next().map(|s| s.map(Record::Fasta)
+                .map_err(ReaderError::Fasta)),
+            NonBamReader::Fastq(r) => r.next().map(|s| s.map(Record::Fastq)
+                .map_err(ReaderError::Fastq)),
+        }
+    }
+}
+
+/// number of records batched into a single message sent from the background
+/// reader thread spawned by `Reader::from_filename_threaded`, to amortize
+/// the cost of synchronization
+const THREADED_CHUNK_SIZE: usize = 100;
+
+/// number of chunks the background reader thread is allowed to get ahead of
+/// the consumer before it blocks
+const THREADED_CHANNEL_BOUND: usize = 4;
+
+/// Iterator returned by `Reader::from_filename_threaded`. Pulls pre-read
+/// chunks of `Record`s off a channel fed by the background reader thread.
+pub struct ThreadedReader {
+    receiver: Receiver<Vec<Result<Record>>>,
+    buffer: std::vec::IntoIter<Result<Record>>,
+}
+
+impl Iterator for ThreadedReader {
+    type Item = Result<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(record) = self.buffer.next() {
+                return Some(record);
+            }
+            match self.receiver.recv() {
+                Ok(chunk) => self.buffer = chunk.into_iter(),
+                Err(_) => return None, // the reader thread is done
+            }
         }
     }
 }
@@ -104,7 +258,18 @@ impl<T: Read> Iterator for Reader<T> {
                 .map_err(|e| ReaderError::Fasta(e))),
             Reader::Fastq(r) => r.next().map(|s| s.map(|t| Record::Fastq(t))
                 .map_err(|e| ReaderError::Fastq(e))),
+            Reader::Bam(reader, record) => {
+                match reader.read(record) {
+                    Ok(_) => Some(Ok(Record::Bam {
+                        id: String::from_utf8_lossy(record.qname())
+                            .into_owned(),
+                        seq: String::from_utf8_lossy(&record.seq().as_bytes())
+                            .into_owned(),
+                    })),
+                    Err(BamReadError::NoMoreRecord) => None,
+                    Err(e) => Some(Err(ReaderError::Other(e.to_string()))),
+                }
+            },
         }
     }
 }
-
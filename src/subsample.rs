@@ -0,0 +1,153 @@
+extern crate flate2;
+extern crate rand;
+
+use seq;
+use std::{result, error, fmt};
+use std::fs::File;
+use std::io::{Write, BufWriter};
+use self::flate2::Compression;
+use self::flate2::write::GzEncoder;
+use self::rand::{Rng, SeedableRng};
+use self::rand::rngs::StdRng;
+
+type Result<T> = result::Result<T, Box<dyn error::Error>>;
+type BoxWrite = Box<dyn Write + Send>;
+
+#[derive(Debug)]
+struct CoverageError {
+    message: String,
+}
+
+impl CoverageError {
+    fn new(message: String) -> Box<CoverageError> {
+        Box::new(CoverageError {
+            message: message,
+        })
+    }
+}
+
+impl fmt::Display for CoverageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl error::Error for CoverageError {}
+
+/// Opens a buffered writer into `path`, gzip-compressing the stream if
+/// `gzip` is set.
+fn open_writer(path: &str, gzip: bool) -> Result<BoxWrite> {
+    if gzip {
+        Ok(Box::new(BufWriter::new(GzEncoder::new(
+            File::create(path)?, Compression::default()))))
+    } else {
+        Ok(Box::new(BufWriter::new(File::create(path)?)))
+    }
+}
+
+/// Sums `seq.len()` across every record in `input_filename`, for a caller
+/// that doesn't already know the dataset's total base count and needs a
+/// throwaway counting pass before calling [keep_probability].
+pub fn count_total_bases(input_filename: &str) -> Result<u64> {
+    let reader = seq::SeqReader::from_path(input_filename)?;
+
+    let mut total = 0u64;
+    for record in reader {
+        total += record?.seq.len() as u64;
+    }
+
+    Ok(total)
+}
+
+/// Computes the per-read keep-probability needed to subsample a dataset
+/// with `total_bases` bases down to `target_coverage * genome_size` bases,
+/// the same way rasusa does it: `p = target_bases / total_bases`. Clamped
+/// to `1.0` if the input is already at or below the target coverage, so
+/// every read is kept rather than none being dropped.
+pub fn keep_probability(total_bases: u64, genome_size: u64,
+                         target_coverage: f64) -> Result<f64> {
+    if total_bases == 0 {
+        return Err(CoverageError::new(
+            "cannot subsample a dataset with zero total bases".to_string()));
+    }
+
+    let target_bases = target_coverage * genome_size as f64;
+    Ok((target_bases / total_bases as f64).min(1.0))
+}
+
+/// Streams `input_filename` to `output_filename`, keeping each record
+/// independently with probability `p`, decided by a `StdRng` seeded with
+/// `seed` so a run can be reproduced. Every kept record's `entry_string` is
+/// written through unchanged, so the original fasta/fastq formatting is
+/// preserved. Returns the number of records kept.
+pub fn subsample(input_filename: &str, output_filename: &str, p: f64,
+                  seed: u64, gzip_output: bool) -> Result<u64> {
+    let reader = seq::SeqReader::from_path(input_filename)?;
+    let mut writer = open_writer(output_filename, gzip_output)?;
+    let mut rng = StdRng::seed_from_u64(seed);
+
+    let mut kept = 0u64;
+    for result in reader {
+        let record = result?;
+        if rng.gen::<f64>() < p {
+            writer.write(record.entry_string.as_bytes())?;
+            kept += 1;
+        }
+    }
+
+    Ok(kept)
+}
+
+/// Like [subsample], but draws one keep/drop decision per mate pair from
+/// `input_filename_1`/`input_filename_2`, so the two mates of a pair are
+/// always kept or dropped together instead of being subsampled
+/// independently. Returns the number of pairs kept.
+pub fn subsample_paired(input_filename_1: &str, input_filename_2: &str,
+                         output_filename_1: &str, output_filename_2: &str,
+                         p: f64, seed: u64, gzip_output: bool) -> Result<u64> {
+    let reader_1 = seq::SeqReader::from_path(input_filename_1)?;
+    let reader_2 = seq::SeqReader::from_path(input_filename_2)?;
+    let mut writer_1 = open_writer(output_filename_1, gzip_output)?;
+    let mut writer_2 = open_writer(output_filename_2, gzip_output)?;
+    let mut rng = StdRng::seed_from_u64(seed);
+
+    let mut kept = 0u64;
+    for (result_1, result_2) in reader_1.zip(reader_2) {
+        let record_1 = result_1?;
+        let record_2 = result_2?;
+        if rng.gen::<f64>() < p {
+            writer_1.write(record_1.entry_string.as_bytes())?;
+            writer_2.write(record_2.entry_string.as_bytes())?;
+            kept += 1;
+        }
+    }
+
+    Ok(kept)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn keep_probability_scales_with_target_coverage() {
+        // 100 total bases, genome size 10 -> 10x actual coverage; asking
+        // for 5x should keep about half
+        let p = keep_probability(100, 10, 5.0).unwrap();
+        assert!((p - 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn keep_probability_clamps_to_one() {
+        // asking for more coverage than the input actually has should never
+        // make us try to upsample
+        let p = keep_probability(100, 10, 50.0).unwrap();
+        assert_eq!(p, 1.0);
+    }
+
+    #[test]
+    fn keep_probability_rejects_zero_total_bases() {
+        assert!(keep_probability(0, 10, 5.0).is_err());
+    }
+}